@@ -0,0 +1,42 @@
+//! Benchmarks for the two ways of reopening a CDB to append to it, reading
+//! the hash tables footer with `Reader::as_writer` or scanning the records
+//! with `Writer::append`.
+//!
+//! Every iteration truncates the footer and writes it back on drop, so the
+//! CDB is the same before and after.
+#[macro_use]
+extern crate criterion;
+extern crate galvanize;
+
+use criterion::Criterion;
+use galvanize::{Reader, Writer};
+use std::env;
+use std::fs::{self, OpenOptions};
+
+fn append(c: &mut Criterion) {
+    for &(name, value_len) in &[("small_values", 8), ("large_values", 4096)] {
+        let path = env::temp_dir().join(format!("galvanize-bench-append-{}.cdb", name));
+        {
+            let mut cdb_writer = Writer::atomic(&path).unwrap();
+            let value = vec![b'v'; value_len];
+            for i in 0..10_000 {
+                cdb_writer.put(format!("key{}", i).as_bytes(), &value).unwrap();
+            }
+            cdb_writer.commit().unwrap();
+        }
+
+        c.bench_function(&format!("as_writer/{}", name), |b| {
+            b.iter(|| {
+                let mut f = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+                Reader::from_file(&mut f).unwrap().as_writer().unwrap().close().unwrap()
+            })
+        });
+        c.bench_function(&format!("append/{}", name), |b| {
+            b.iter(|| Writer::append(&path).unwrap().close().unwrap())
+        });
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+criterion_group!(benches, append);
+criterion_main!(benches);
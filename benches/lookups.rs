@@ -0,0 +1,53 @@
+//! Benchmarks for the lookup and scan paths of `Reader`.
+//!
+//! Run them with `cargo bench`, the results of previous runs are kept under
+//! `target/criterion` and used as the baseline to compare against.
+#[macro_use]
+extern crate criterion;
+extern crate galvanize;
+
+use criterion::Criterion;
+use galvanize::Reader;
+use std::fs;
+use std::hint::black_box;
+
+/// `top250pws.cdb` has unique keys, `pwdump.cdb` has many colliding ones.
+const DATABASES: &[(&str, &str)] = &[
+    ("unique", "tests/testdata/top250pws.cdb"),
+    ("collisions", "tests/testdata/pwdump.cdb"),
+];
+
+fn lookups(c: &mut Criterion) {
+    for &(name, path) in DATABASES {
+        let bytes = fs::read(path).unwrap();
+        let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+        let keys = cdb_reader.keys();
+        let missing: Vec<Vec<u8>> = (0..keys.len())
+            .map(|i| format!("missing key {}", i).into_bytes())
+            .collect();
+
+        c.bench_function(&format!("get_first/{}/hit", name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % keys.len();
+                black_box(cdb_reader.get_first(&keys[i]).unwrap())
+            })
+        });
+        c.bench_function(&format!("get_first/{}/miss", name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % missing.len();
+                black_box(cdb_reader.get_first(&missing[i]).is_err())
+            })
+        });
+        c.bench_function(&format!("into_iter/{}", name), |b| {
+            b.iter(|| black_box((&mut cdb_reader).into_iter().count()))
+        });
+        c.bench_function(&format!("scan_buffered/{}", name), |b| {
+            b.iter(|| black_box(cdb_reader.scan_buffered().count()))
+        });
+    }
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+//! Benchmarks for writing a CDB with 1M records in memory, with and without
+//! reserving room in the `Writer` index up front.
+//!
+//! The number of allocations needed by each is printed before timing them.
+#[macro_use]
+extern crate criterion;
+extern crate galvanize;
+
+use criterion::Criterion;
+use galvanize::Writer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the allocations and reallocations done through it.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const RECORDS: usize = 1_000_000;
+
+fn write(keys: &[[u8; 8]], sized: bool) -> usize {
+    // Room for every record, so that only the index grows while writing.
+    let mut c = Cursor::new(Vec::with_capacity(RECORDS * 40));
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    {
+        let mut cdb_writer = if sized {
+            Writer::with_capacity(&mut c, RECORDS).unwrap()
+        } else {
+            Writer::new(&mut c).unwrap()
+        };
+        for key in keys {
+            cdb_writer.put(key, b"").unwrap();
+        }
+    }
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn writes(c: &mut Criterion) {
+    let keys: Vec<[u8; 8]> = (0..RECORDS as u64).map(u64::to_le_bytes).collect();
+    for &(name, sized) in &[("new", false), ("with_capacity", true)] {
+        println!("{}: {} allocations for {} records", name, write(&keys, sized), RECORDS);
+    }
+
+    let mut group = c.benchmark_group("write_1m");
+    group.sample_size(10);
+    group.bench_function("new", |b| b.iter(|| write(&keys, false)));
+    group.bench_function("with_capacity", |b| b.iter(|| write(&keys, true)));
+    group.finish();
+}
+
+criterion_group!(benches, writes);
+criterion_main!(benches);
@@ -7,11 +7,11 @@ extern crate rustc_serialize;
 #[cfg(feature = "cli")]
 mod cli {
     use docopt::Docopt;
-    use galvanize::helpers::vec2str;
-    use galvanize::Reader;
-    use std::cmp::min;
+    use galvanize::helpers::{decode_key, vec2str, write_cdbmake};
+    use galvanize::{Error, Reader};
     use std::env;
     use std::fs::File;
+    use std::io::{self, Read, Seek, Write};
     use std::process;
 
     const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
@@ -26,14 +26,54 @@ mod cli {
         cmd_tail: bool,
         arg_COUNT: u32,
         cmd_count: bool,
+        flag_encoded: bool,
         cmd_all: bool,
+        cmd_verify: bool,
+        cmd_map: bool,
+        flag_quiet: bool,
+        flag_format: String,
         flag_version: bool,
     }
 
+    /// The raw bytes of the `<key>` argument, hex decoded when `-e` is given.
+    fn raw_key(args: &Args) -> Vec<u8> {
+        let key = &args.arg_key;
+        if !args.flag_encoded {
+            return key.as_bytes().to_vec();
+        }
+        decode_key(key).unwrap_or_else(|e| {
+            eprintln!("{:?} is not a valid hex encoded key: {}", key, e);
+            process::exit(1);
+        })
+    }
+
     fn display_items(item: (Vec<u8>, Vec<u8>)) {
         println!("{:?}: {:?}", vec2str(&item.0), vec2str(&item.1));
     }
 
+    /// Show the `items` in the output format selected in `args`.
+    fn show_items<I: Iterator<Item = (Vec<u8>, Vec<u8>)>>(args: &Args, items: I) {
+        match &args.flag_format[..] {
+            "text" => items.for_each(display_items),
+            "cdbmake" => {
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                let written = items
+                    .map(|(k, v)| write_cdbmake(&mut out, &k, &v))
+                    .collect::<io::Result<()>>()
+                    .and_then(|_| out.write_all(b"\n"));
+                if let Err(e) = written {
+                    eprintln!("Could not write to stdout: {}", e);
+                    process::exit(1);
+                }
+            }
+            format => {
+                eprintln!("Unknown output format {:?}", format);
+                process::exit(1);
+            }
+        }
+    }
+
     pub fn main() {
         let bin = match env::current_exe() {
             Ok(bin) => bin.file_name().map(|f| f.to_string_lossy().into_owned()),
@@ -46,19 +86,32 @@ mod cli {
              {0:}
 
              Usage:
-               {0:} FILE (top|tail)
-               {0:} FILE (top|tail) COUNT
+               {0:} FILE (top|tail) [--format=<fmt>]
+               {0:} FILE (top|tail) COUNT [--format=<fmt>]
                {0:} FILE count
+               {0:} FILE count <key>
+               {0:} FILE count -e <key>
                {0:} FILE get <key>
                {0:} FILE get -e <key>
                {0:} FILE all --yes-i-am-sure
+               {0:} FILE verify [--quiet]
+               {0:} FILE map
                {0:} (-h | --help)
                {0:} --version
 
+             FILE can be `-` to read the CDB from stdin, which will be fully
+             buffered in memory.
+
              Options:
                -h --help      Show this screen.
                --version      Show version.
-               -e, --encoded  Treat the key as encoded.
+               -e, --encoded  Treat the key as hex encoded bytes, like
+                              `6b6579` for `key`.
+               -q, --quiet    Only report the result of `verify` through the
+                              exit code.
+               --format=<fmt> How `top` and `tail` show records, either `text`
+                              or `cdbmake` for byte exact output that can be
+                              fed back into `cdbmake` [default: text].
              ",
             bin
         ))
@@ -70,43 +123,121 @@ mod cli {
             process::exit(0);
         }
 
-        let filename = args.arg_FILE;
-        let mut f = match File::open(filename.clone()) {
-            Ok(f) => f,
-            Err(e) => {
-                println!("Could not open file {:?}: {:?}", filename, e);
+        let filename = args.arg_FILE.clone();
+        if filename == "-" {
+            // A CDB needs random access, so stdin is buffered fully in memory.
+            let mut bytes = vec![];
+            if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+                if !args.flag_quiet {
+                    eprintln!("Could not read from stdin: {}", e);
+                }
                 process::exit(1);
             }
-        };
-        let mut cdb_reader = match Reader::new(&mut f) {
-            Ok(f) => f,
-            Err(e) => {
-                println!("Could not use {:?} as a readonly CDB: {:?}", filename, e);
-                process::exit(1);
+            match Reader::from_bytes(bytes) {
+                Ok(cdb_reader) => run(&args, cdb_reader),
+                Err(e) => invalid_cdb(&args, e),
             }
-        };
+        } else {
+            let mut f = match File::open(filename.clone()) {
+                Ok(f) => f,
+                Err(e) => {
+                    if !args.flag_quiet {
+                        eprintln!("Could not open file {:?}: {}", filename, e);
+                    }
+                    process::exit(1);
+                }
+            };
+            match Reader::new(&mut f) {
+                Ok(cdb_reader) => run(&args, cdb_reader),
+                Err(e) => invalid_cdb(&args, e),
+            }
+        }
+    }
 
+    fn invalid_cdb(args: &Args, e: Error) -> ! {
+        if !args.flag_quiet {
+            eprintln!("Could not use {:?} as a readonly CDB: {}", args.arg_FILE, e);
+        }
+        process::exit(1);
+    }
+
+    fn run<F: Read + Seek>(args: &Args, mut cdb_reader: Reader<F>) {
+        let filename = &args.arg_FILE;
         let count: usize = if args.arg_COUNT == 0 {
             10
         } else {
             args.arg_COUNT as usize
         };
 
-        if args.cmd_all {
+        if args.cmd_verify {
+            // Check the structural integrity of the CDB.
+            let report = match cdb_reader.verify() {
+                Ok(report) => report,
+                Err(e) => {
+                    if !args.flag_quiet {
+                        eprintln!("Could not verify {:?}: {}", filename, e);
+                    }
+                    process::exit(1);
+                }
+            };
+            if !args.flag_quiet {
+                for anomaly in &report.anomalies {
+                    eprintln!("{}", anomaly);
+                }
+                println!(
+                    "{:?}: {} records in {} bytes, {} problems found",
+                    filename,
+                    report.records,
+                    report.total_bytes,
+                    report.anomalies.len()
+                );
+            }
+            process::exit(if report.is_ok() { 0 } else { 1 });
+        } else if args.cmd_map {
+            // Show how full each of the 256 hash tables is.
+            const WIDTH: u32 = 40;
+            for bucket in 0..=255u8 {
+                let info = match cdb_reader.table_info(bucket) {
+                    Ok(info) => info,
+                    Err(e) => {
+                        eprintln!("Could not read table {} of {:?}: {}", bucket, filename, e);
+                        process::exit(1);
+                    }
+                };
+                let filled = if info.slots == 0 {
+                    0
+                } else {
+                    (u64::from(info.occupied) * u64::from(WIDTH) / u64::from(info.slots)) as u32
+                };
+                println!(
+                    "{:3} {:>8} slots {:>8} used |{}{}|",
+                    bucket,
+                    info.slots,
+                    info.occupied,
+                    "#".repeat(filled as usize),
+                    ".".repeat((WIDTH - filled) as usize)
+                );
+            }
+        } else if args.cmd_all {
             // Show all (key, value) pairs.
             for item in cdb_reader.into_iter() {
                 display_items(item);
             }
         } else if args.cmd_top {
             // Show COUNT first (key, value) pairs.
-            for item in cdb_reader.into_iter().take(count) {
-                display_items(item);
-            }
+            show_items(args, cdb_reader.into_iter().take(count));
         } else if args.cmd_tail {
             // Show COUNT last (key, value) pairs.
-            let len = cdb_reader.len();
-            for item in cdb_reader.into_iter().skip(len - min(len, count)) {
-                display_items(item);
+            show_items(args, cdb_reader.tail(count));
+        } else if args.cmd_count && !args.arg_key.is_empty() {
+            // How many values are there under a single key?
+            let key = &args.arg_key;
+            match cdb_reader.count(&raw_key(args)) {
+                Ok(count) => println!("There are {} values under {:?}", count, key),
+                Err(e) => {
+                    eprintln!("Could not look up {:?} in {:?}: {}", key, filename, e);
+                    process::exit(1);
+                }
             }
         } else if args.cmd_count {
             // How many (key, value) are there in this file?
@@ -117,8 +248,8 @@ mod cli {
             );
         } else if args.cmd_get {
             // Get all values under a single key.
-            let key = args.arg_key;
-            let values = cdb_reader.get(&key.clone().into_bytes());
+            let key = &args.arg_key;
+            let values = cdb_reader.get(&raw_key(args));
             if values.is_empty() {
                 println!("There're no values under {:?}", key);
             } else if values.len() == 1 {
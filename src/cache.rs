@@ -0,0 +1,108 @@
+//! This module allows you to cache the values of frequently read keys.
+use reader::Reader;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek};
+use types::{Error, Result};
+
+/// Wraps a [`Reader`](../reader/struct.Reader.html), keeping the values of the
+/// most recently used keys in memory.
+///
+/// Since a CDB is constant, cached values never need to be invalidated. This
+/// is useful for skewed workloads, where a small set of keys accounts for
+/// most lookups.
+///
+/// #Example
+///
+/// ```
+/// use galvanize::{CachingReader, Reader};
+/// use std::fs::File;
+///
+/// let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+/// let cdb_reader = Reader::new(&mut f).unwrap();
+/// let mut cache = CachingReader::new(cdb_reader, 16);
+///
+/// // The first lookup reads from the file, the second one from memory.
+/// assert_eq!(cache.get("letmein".as_bytes()), vec!["10".as_bytes()]);
+/// assert_eq!(cache.get_first("letmein".as_bytes()).unwrap(), "10".as_bytes());
+/// ```
+#[derive(Debug)]
+pub struct CachingReader<'a, F: Read + Seek + 'a> {
+    /// `Reader` used on cache misses.
+    reader: Reader<'a, F>,
+    /// How many keys can be cached at most.
+    capacity: usize,
+    /// Cached values, alongside when their key was last used.
+    entries: HashMap<Vec<u8>, (Vec<Vec<u8>>, u64)>,
+    /// Cached keys, ordered from least to most recently used.
+    recency: BTreeMap<u64, Vec<u8>>,
+    /// Monotonic counter used to track when keys are used.
+    tick: u64,
+}
+
+impl<'a, F: Read + Seek + 'a> CachingReader<'a, F> {
+    /// Creates a new `CachingReader` that caches the values of up to
+    /// `capacity` keys read through `reader`.
+    pub fn new(reader: Reader<'a, F>, capacity: usize) -> CachingReader<'a, F> {
+        CachingReader {
+            reader,
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Return a `Vec` of all the values under the given `key`.
+    pub fn get(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        if self.capacity == 0 {
+            return self.reader.get(key);
+        }
+        match self.fetch(key) {
+            Ok(values) => values.clone(),
+            // Not cached, defer to the `Reader` for the values it can read.
+            Err(_) => self.reader.get(key),
+        }
+    }
+
+    /// Pull the `value` bytes for the first occurence of the given `key`.
+    pub fn get_first(&mut self, key: &[u8]) -> Result<Vec<u8>> {
+        if self.capacity == 0 {
+            return self.reader.get_first(key);
+        }
+        match self.fetch(key)?.first() {
+            Some(value) => Ok(value.clone()),
+            None => Err(Error::KeyNotInCDB),
+        }
+    }
+
+    /// Consume this `CachingReader`, returning the wrapped `Reader`.
+    pub fn into_inner(self) -> Reader<'a, F> {
+        self.reader
+    }
+
+    /// Get the values under `key` from the cache, reading and caching them on
+    /// a miss. Values are only cached if all of them could be read.
+    ///
+    /// The `capacity` must not be `0`.
+    fn fetch(&mut self, key: &[u8]) -> Result<&Vec<Vec<u8>>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.1);
+            self.recency.insert(tick, key.to_vec());
+            entry.1 = tick;
+        } else {
+            let values = self.reader.get_values(key).collect::<Result<Vec<_>>>()?;
+            if self.entries.len() >= self.capacity {
+                // Evict the least recently used key.
+                let oldest = *self.recency.keys().next().unwrap();
+                let evicted = self.recency.remove(&oldest).unwrap();
+                self.entries.remove(&evicted);
+            }
+            self.recency.insert(tick, key.to_vec());
+            self.entries.insert(key.to_vec(), (values, tick));
+        }
+        Ok(&self.entries[key].0)
+    }
+}
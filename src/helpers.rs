@@ -1,7 +1,28 @@
 //! Various functions that are used across both the writer and reader modules.
 //!
-//! You shouldn't need to use this module directly.
+//! You shouldn't need to use this module directly to read or write a CDB.
+//! [`hash`](fn.hash.html), [`pack`](fn.pack.html),
+//! [`unpack`](fn.unpack.html) and the size constants are the primitives of
+//! the on-disk format, and
+//! are a stable part of the API for building CDB compatible tooling, like
+//! patching the pointers in the header of an existing file.
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::num::Wrapping;
+use std::ops::{Deref, DerefMut};
+use std::str;
+use types::{Error, Result};
+
+/// Size in bytes of the header at the start of every standard CDB, which
+/// holds the position and number of slots of each of the 256 hash tables.
+/// See [`Format`](enum.Format.html) for the sizes in other formats.
+pub const HEADER_SIZE: usize = 2048;
+
+/// Size in bytes of a hash table slot, a hash followed by a record position.
+pub const SLOT_SIZE: usize = 8;
+
+/// Size in bytes of the key and value lengths at the start of every record.
+pub const RECORD_HEADER_SIZE: usize = 8;
 
 /// DJB hash function
 ///
@@ -17,21 +38,399 @@ pub fn hash(string: &[u8]) -> u32 {
 }
 
 /// Get array of bytes from an `u32`.
+///
+/// This is the little-endian encoding used by the CDB format for every
+/// position, length and hash value.
+///
+/// ```
+/// use galvanize::helpers::pack;
+///
+/// assert_eq!(pack(2048), [0x00, 0x08, 0x00, 0x00]);
+/// ```
 #[inline]
 pub fn pack(v: u32) -> [u8; 4] {
     [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
 }
 
 /// Get an `u32` from an array of 4 bytes.
+///
+/// This is the inverse of [`pack`](fn.pack.html).
+///
+/// ```
+/// use galvanize::helpers::{pack, unpack};
+///
+/// assert_eq!(unpack([0x00, 0x08, 0x00, 0x00]), 2048);
+/// assert_eq!(unpack(pack(0xdeadbeef)), 0xdeadbeef);
+/// ```
 #[inline]
 pub fn unpack(v: [u8; 4]) -> u32 {
     (v[0] as u32) | ((v[1] as u32) << 8) | ((v[2] as u32) << 16) | ((v[3] as u32) << 24)
 }
 
+/// Byte order used to store the 32-bit quantities of a CDB.
+///
+/// The CDB specification mandates little-endian, but some legacy tools
+/// produced otherwise identical files using big-endian.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    /// Little-endian, as per the CDB specification.
+    #[default]
+    Little,
+    /// Big-endian.
+    Big,
+}
+
+impl Endianness {
+    /// Get array of bytes from an `u32` in this byte order.
+    #[inline]
+    pub fn pack(self, v: u32) -> [u8; 4] {
+        match self {
+            Endianness::Little => v.to_le_bytes(),
+            Endianness::Big => v.to_be_bytes(),
+        }
+    }
+
+    /// Get an `u32` from an array of 4 bytes in this byte order.
+    #[inline]
+    pub fn unpack(self, v: [u8; 4]) -> u32 {
+        match self {
+            Endianness::Little => u32::from_le_bytes(v),
+            Endianness::Big => u32::from_be_bytes(v),
+        }
+    }
+}
+
+/// Width of the positions and lengths stored in a CDB.
+///
+/// Standard CDBs store them as 32-bit quantities, which limits them to 4GB.
+/// The cdb64 variant stores them as 64-bit quantities instead, doubling the
+/// size of the header pointers, hash table slots and record headers. Keys and
+/// values are still limited to 4GB each, and hashes are still 32-bit DJB
+/// hashes, stored in 64 bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Standard CDB, as per the specification.
+    #[default]
+    Cdb32,
+    /// 64-bit CDB, for files larger than 4GB.
+    Cdb64,
+}
+
+impl Format {
+    /// Size in bytes of the header at the start of the CDB.
+    pub fn header_size(self) -> usize {
+        256 * self.pair_size()
+    }
+
+    /// Size in bytes of a hash table slot.
+    pub fn slot_size(self) -> usize {
+        self.pair_size()
+    }
+
+    /// Size in bytes of the key and value lengths at the start of a record.
+    pub fn record_header_size(self) -> usize {
+        self.pair_size()
+    }
+
+    /// Largest position or length that can be stored.
+    pub fn max_value(self) -> u64 {
+        match self {
+            Format::Cdb32 => u64::from(u32::MAX),
+            Format::Cdb64 => u64::MAX,
+        }
+    }
+
+    /// Size in bytes of the header pointers, slots and record headers, which
+    /// are all a pair of quantities.
+    pub(crate) fn pair_size(self) -> usize {
+        match self {
+            Format::Cdb32 => 8,
+            Format::Cdb64 => 16,
+        }
+    }
+
+    /// Get the pair of quantities stored in `buf`, which is `pair_size` bytes.
+    pub(crate) fn unpack_pair(self, endianness: Endianness, buf: &[u8]) -> (u64, u64) {
+        match self {
+            Format::Cdb32 => (
+                u64::from(endianness.unpack([buf[0], buf[1], buf[2], buf[3]])),
+                u64::from(endianness.unpack([buf[4], buf[5], buf[6], buf[7]])),
+            ),
+            Format::Cdb64 => {
+                let word = |i: usize| {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(&buf[i..i + 8]);
+                    match endianness {
+                        Endianness::Little => u64::from_le_bytes(bytes),
+                        Endianness::Big => u64::from_be_bytes(bytes),
+                    }
+                };
+                (word(0), word(8))
+            }
+        }
+    }
+
+    /// Append the pair of quantities `a` and `b` to `out`. They are truncated
+    /// to 32 bits for `Cdb32`.
+    pub(crate) fn pack_pair(self, endianness: Endianness, a: u64, b: u64, out: &mut Vec<u8>) {
+        match self {
+            Format::Cdb32 => {
+                out.extend_from_slice(&endianness.pack(a as u32));
+                out.extend_from_slice(&endianness.pack(b as u32));
+            }
+            Format::Cdb64 => {
+                for word in &[a, b] {
+                    out.extend_from_slice(&match endianness {
+                        Endianness::Little => word.to_le_bytes(),
+                        Endianness::Big => word.to_be_bytes(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Write the `key` and `value` of a record into `out` in the format used by
+/// `cdbmake` and `cdbdump`, `+klen,dlen:key->value` followed by a newline.
+///
+/// The `key` and `value` are written as is, so the output is byte exact. A
+/// whole dump is terminated by an empty line after the last record.
+///
+/// ```
+/// use galvanize::helpers::write_cdbmake;
+///
+/// let mut out = vec![];
+/// write_cdbmake(&mut out, b"key", b"value").unwrap();
+/// assert_eq!(out, b"+3,5:key->value\n");
+/// ```
+pub fn write_cdbmake<W: Write>(out: &mut W, key: &[u8], value: &[u8]) -> io::Result<()> {
+    write!(out, "+{},{}:", key.len(), value.len())?;
+    out.write_all(key)?;
+    out.write_all(b"->")?;
+    out.write_all(value)?;
+    out.write_all(b"\n")
+}
+
 /// Represent an iterable of bytes as "lossy" `utf8` `String`.
 ///
-/// If the byte cannot be represented as an `utf8` character, it'll be replaced
-/// with a `?`.
+/// Invalid `utf8` sequences are replaced with the Unicode replacement
+/// character `U+FFFD`, like `String::from_utf8_lossy` does.
 pub fn vec2str(v: &[u8]) -> String {
-    String::from_utf8_lossy(v).into_owned()
+    vec2str_with(v, char::REPLACEMENT_CHARACTER)
+}
+
+/// Represent an iterable of bytes as "lossy" `utf8` `String`, replacing
+/// invalid `utf8` sequences with `placeholder`.
+///
+/// ```
+/// use galvanize::helpers::vec2str_with;
+///
+/// assert_eq!(vec2str_with(b"pass\xffword", '?'), "pass?word");
+/// ```
+pub fn vec2str_with(v: &[u8], placeholder: char) -> String {
+    let mut out = String::with_capacity(v.len());
+    let mut rest = v;
+    loop {
+        match str::from_utf8(rest) {
+            Ok(valid) => {
+                out.push_str(valid);
+                return out;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                out.push_str(str::from_utf8(valid).unwrap_or_default());
+                out.push(placeholder);
+                // An incomplete sequence at the end has no `error_len`.
+                rest = &invalid[e.error_len().unwrap_or(invalid.len())..];
+            }
+        }
+    }
+}
+
+/// Decode a key written as pairs of hex digits, in either case, into its raw
+/// bytes, so that binary keys can be passed around as text.
+///
+/// An invalid digit, or a missing one at the end, is reported as
+/// `Error::MalformedKey` with the byte offset of the digit.
+///
+/// ```
+/// use galvanize::helpers::decode_key;
+///
+/// assert_eq!(decode_key("6b65FF00").unwrap(), b"ke\xff\x00");
+/// assert!(decode_key("6b6").is_err());
+/// ```
+pub fn decode_key(encoded: &str) -> Result<Vec<u8>> {
+    let digits = encoded.as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return Err(Error::MalformedKey {
+            offset: digits.len(),
+        });
+    }
+    let digit = |offset: usize| match char::from(digits[offset]).to_digit(16) {
+        Some(d) => Ok(d as u8),
+        None => Err(Error::MalformedKey { offset }),
+    };
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| Ok(digit(i)? << 4 | digit(i + 1)?))
+        .collect()
+}
+
+/// Order in which the slots of a hash table are probed, both when looking up
+/// a key and when placing it in the table.
+///
+/// Standard CDBs use [`LinearProbe`](struct.LinearProbe.html). Other orders
+/// are only needed to interoperate with CDB variants, and the resulting files
+/// can't be read by standard tools. The sequence must visit every one of the
+/// `nslots` slots within `nslots` attempts, otherwise keys could be left out
+/// of the tables.
+pub trait ProbeSequence: fmt::Debug + Sync {
+    /// Slot to probe on the `attempt`-th try, counting from `0`, in a table
+    /// with `nslots`, starting at slot `first`.
+    fn slot(&self, first: u32, attempt: u32, nslots: u32) -> u32;
+}
+
+/// Probe the slot after the previous one, wrapping around to the start of
+/// the table, as standard CDBs do.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinearProbe;
+
+impl ProbeSequence for LinearProbe {
+    fn slot(&self, first: u32, attempt: u32, nslots: u32) -> u32 {
+        ((u64::from(first) + u64::from(attempt)) % u64::from(nslots)) as u32
+    }
+}
+
+/// A hash function for the keys of a CDB, used to pick both the hash table a
+/// key goes in and its first slot.
+///
+/// Standard CDBs use the DJB hash, see [`DjbHasher`](struct.DjbHasher.html).
+/// A CDB written with any other hash function can only be read with that
+/// same function, so the result isn't compatible with other CDB tools.
+pub trait Hasher: fmt::Debug + Sync {
+    /// Hash of `key`.
+    fn hash(&self, key: &[u8]) -> u32;
+}
+
+/// The DJB hash function, as used by standard CDBs. See [`hash`](fn.hash.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DjbHasher;
+
+impl Hasher for DjbHasher {
+    fn hash(&self, key: &[u8]) -> u32 {
+        hash(key)
+    }
+}
+
+/// Hash of a key, as stored in a hash table slot.
+///
+/// Slots hold a hash and a position, both 32-bit quantities, so they are kept
+/// in distinct types to avoid mixing them up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Hash(pub(crate) u32);
+
+impl Hash {
+    /// Number of the hash table keys with this hash are stored in.
+    pub(crate) fn bucket(self) -> usize {
+        (self.0 & 0xff) as usize
+    }
+
+    /// Slot where probing starts for this hash, in a table with `nslots`.
+    pub(crate) fn slot(self, nslots: u32) -> u32 {
+        (self.0 >> 8) % nslots
+    }
+}
+
+/// Position of a record in the file, as stored in a hash table slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Pos(pub(crate) u64);
+
+/// The underlying file of a `Reader` or `Writer`, either borrowed from the
+/// caller or owned by it.
+#[derive(Debug)]
+pub(crate) enum Handle<'a, F: 'a> {
+    Borrowed(&'a mut F),
+    Owned(F),
+}
+
+impl<'a, F: 'a> Deref for Handle<'a, F> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        match *self {
+            Handle::Borrowed(ref f) => f,
+            Handle::Owned(ref f) => f,
+        }
+    }
+}
+
+impl<'a, F: 'a> DerefMut for Handle<'a, F> {
+    fn deref_mut(&mut self) -> &mut F {
+        match *self {
+            Handle::Borrowed(ref mut f) => f,
+            Handle::Owned(ref mut f) => f,
+        }
+    }
+}
+
+/// A view of `inner` where byte `0` is at position `base`, used to embed a CDB
+/// inside a larger file. See `Reader::new_at` and `Writer::new_at`.
+///
+/// Seeking before `base` is an error.
+#[derive(Debug)]
+pub struct Offset<F> {
+    inner: F,
+    base: u64,
+}
+
+impl<F> Offset<F> {
+    /// Creates a view of `inner` that starts at position `base`.
+    pub fn new(inner: F, base: u64) -> Offset<F> {
+        Offset { inner, base }
+    }
+
+    /// Position in the underlying file where this view starts.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Unwraps this `Offset`, returning the underlying file.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn relative(&self, pos: u64) -> io::Result<u64> {
+        pos.checked_sub(self.base).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a position before the start of the CDB",
+            )
+        })
+    }
+}
+
+impl<F: Read> Read for Offset<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<F: Write> Write for Offset<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<F: Seek> Seek for Offset<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let absolute = match pos {
+            SeekFrom::Start(n) => self.inner.seek(SeekFrom::Start(self.base.saturating_add(n)))?,
+            pos => self.inner.seek(pos)?,
+        };
+        self.relative(absolute)
+    }
 }
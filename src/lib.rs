@@ -138,11 +138,30 @@
 //! # }
 //! ```
 
+#[cfg(feature = "arrayvec")]
+extern crate arrayvec;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "serde")]
+extern crate serde_crate as serde;
+
+pub mod cache;
 pub mod helpers;
+pub mod normalize;
 pub mod reader;
+pub mod rw;
+pub mod shared;
 pub mod types;
 pub mod writer;
 
-pub use reader::Reader;
-pub use types::{Error, Result};
-pub use writer::Writer;
+pub use cache::CachingReader;
+pub use helpers::{Endianness, Format};
+pub use normalize::{NormalizingReader, NormalizingWriter};
+pub use reader::{CdbRead, Reader};
+pub use rw::RwCdb;
+pub use shared::SharedReader;
+pub use types::{
+    CdbMap, Error, LookupProfile, Result, SizeBreakdown, Stats, TableInfo, TableStats, ValueHandle,
+    VerifyReport,
+};
+pub use writer::{AtomicWriter, BufferedWriter, Entry, Writer};
@@ -0,0 +1,108 @@
+//! This module allows you to normalize keys, like lowercasing them, in a
+//! single place for both writing and reading a CDB.
+use reader::Reader;
+use std::io::{Read, Seek, Write};
+use types::Result;
+use writer::Writer;
+
+/// Wraps a [`Writer`](../writer/struct.Writer.html), applying `transform` to
+/// every key before writing it.
+///
+/// The CDB must be read through a [`NormalizingReader`](struct.NormalizingReader.html)
+/// using the exact same `transform`, otherwise lookups will miss.
+///
+/// #Example
+///
+/// ```
+/// use galvanize::{NormalizingWriter, Writer};
+/// use std::io::Cursor;
+///
+/// fn lowercase(key: &[u8]) -> Vec<u8> {
+///     key.to_ascii_lowercase()
+/// }
+///
+/// let mut c = Cursor::new(vec![]);
+/// let mut cdb_writer = NormalizingWriter::new(Writer::new(&mut c).unwrap(), lowercase);
+/// cdb_writer.put("Someone@Example.com".as_bytes(), "1".as_bytes()).unwrap();
+///
+/// let mut cdb_reader = cdb_writer.as_reader().unwrap();
+/// assert_eq!(cdb_reader.get("someone@example.COM".as_bytes()), vec!["1".as_bytes()]);
+/// ```
+pub struct NormalizingWriter<'a, F: Write + Read + Seek + 'a> {
+    /// `Writer` the normalized keys are written to.
+    writer: Writer<'a, F>,
+    /// Normalization applied to every key.
+    transform: fn(&[u8]) -> Vec<u8>,
+}
+
+impl<'a, F: Write + Read + Seek + 'a> NormalizingWriter<'a, F> {
+    /// Creates a new `NormalizingWriter` that writes to `writer` the keys
+    /// normalized by `transform`.
+    pub fn new(writer: Writer<'a, F>, transform: fn(&[u8]) -> Vec<u8>) -> NormalizingWriter<'a, F> {
+        NormalizingWriter { writer, transform }
+    }
+
+    /// Write `value` for the normalized `key` into this CDB.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.writer.put(&(self.transform)(key), value)
+    }
+
+    /// Consume this `NormalizingWriter`, returning the wrapped `Writer`.
+    pub fn into_inner(self) -> Writer<'a, F> {
+        self.writer
+    }
+
+    /// Transform this `NormalizingWriter` into a `NormalizingReader` with the
+    /// same `transform`, using the same underlying `file`.
+    pub fn as_reader(self) -> Result<NormalizingReader<'a, F>> {
+        let transform = self.transform;
+        Ok(NormalizingReader::new(self.writer.as_reader()?, transform))
+    }
+}
+
+/// Wraps a [`Reader`](../reader/struct.Reader.html), applying `transform` to
+/// every key before looking it up.
+///
+/// The `transform` must be the same one the CDB was written with, see
+/// [`NormalizingWriter`](struct.NormalizingWriter.html).
+#[derive(Debug)]
+pub struct NormalizingReader<'a, F: Read + Seek + 'a> {
+    /// `Reader` the normalized keys are looked up in.
+    reader: Reader<'a, F>,
+    /// Normalization applied to every key.
+    transform: fn(&[u8]) -> Vec<u8>,
+}
+
+impl<'a, F: Read + Seek + 'a> NormalizingReader<'a, F> {
+    /// Creates a new `NormalizingReader` that looks up the keys normalized by
+    /// `transform` in `reader`.
+    pub fn new(reader: Reader<'a, F>, transform: fn(&[u8]) -> Vec<u8>) -> NormalizingReader<'a, F> {
+        NormalizingReader { reader, transform }
+    }
+
+    /// Return a `Vec` of all the values under the normalized `key`.
+    pub fn get(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        self.reader.get(&(self.transform)(key))
+    }
+
+    /// Pull the `value` bytes for the first occurence of the normalized `key`.
+    pub fn get_first(&mut self, key: &[u8]) -> Result<Vec<u8>> {
+        self.reader.get_first(&(self.transform)(key))
+    }
+
+    /// Pull the `value` bytes for the `index`st occurence of the normalized
+    /// `key`.
+    pub fn get_from_pos(&mut self, key: &[u8], index: u32) -> Result<Vec<u8>> {
+        self.reader.get_from_pos(&(self.transform)(key), index)
+    }
+
+    /// How many values are stored under the normalized `key`.
+    pub fn count(&mut self, key: &[u8]) -> Result<usize> {
+        self.reader.count(&(self.transform)(key))
+    }
+
+    /// Consume this `NormalizingReader`, returning the wrapped `Reader`.
+    pub fn into_inner(self) -> Reader<'a, F> {
+        self.reader
+    }
+}
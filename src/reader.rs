@@ -1,8 +1,27 @@
 //! This module allows you to read from a CDB.
-use helpers::{hash, unpack};
+#[cfg(feature = "arrayvec")]
+use arrayvec::ArrayVec;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use helpers::{
+    write_cdbmake, DjbHasher, Endianness, Format, Handle, Hash, Hasher, LinearProbe, Offset, Pos,
+    ProbeSequence,
+};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use types::{Error, Result};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::mem;
+use std::path::Path;
+use std::result;
+use std::sync::Arc;
+use std::vec;
+use types::{
+    Anomaly, Error, LookupProfile, Result, SizeBreakdown, Stats, TableInfo, TableStats,
+    ValueHandle, VerifyReport,
+};
 use writer::Writer;
 
 /// Allows you to read from CDB.
@@ -61,13 +80,25 @@ use writer::Writer;
 #[derive(Debug)]
 pub struct Reader<'a, F: Read + Seek + 'a> {
     /// Opened file to read values from.
-    file: &'a mut F,
+    file: Handle<'a, F>,
     /// Index for the contents of the CDB.
-    index: Vec<(u32, u32)>,
+    index: Vec<(u64, u32)>,
     /// Position in the file where the hash table starts.
-    table_start: usize,
+    table_start: u64,
     /// How many elements are there in the CDB.
     length: usize,
+    /// Size of the `file` in bytes.
+    file_len: u64,
+    /// Byte order of the 32-bit quantities in the `file`.
+    endianness: Endianness,
+    /// Width of the quantities in the `file`.
+    format: Format,
+    /// How many bytes of the `file` are held in memory, if any.
+    in_memory: usize,
+    /// Order in which hash table slots are probed.
+    probing: &'static dyn ProbeSequence,
+    /// Hash function the keys were written with.
+    hasher: &'static dyn Hasher,
 }
 
 /// Iterator struct for Key, Values in a CDB.
@@ -81,37 +112,184 @@ impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for ItemIterator<'a, 'file,
     type Item = (Vec<u8>, Vec<u8>);
 
     /// Fetch the next (`key`, `value`) pair, if any.
+    ///
+    /// The iteration stops early at a corrupt record, see
+    /// [`Reader::try_iter`](struct.Reader.html#method.try_iter) to find out
+    /// whether that happened.
     fn next(&mut self) -> Option<Self::Item> {
-        match self.reader.file.seek(SeekFrom::Current(0)) {
-            Ok(pos) => {
-                if pos >= self.reader.table_start as u64 {
-                    return None;
-                }
+        self.reader.next_item().ok()?
+    }
+}
+
+impl<'a, 'file: 'a, F: Read + Seek + 'file> ItemIterator<'a, 'file, F> {
+    /// Position in the file of the next record, which can be passed to
+    /// [`Reader::iter_from`](struct.Reader.html#method.iter_from) to resume
+    /// the iteration later on.
+    pub fn offset(&mut self) -> Result<u64> {
+        Ok(self.reader.file.stream_position()?)
+    }
+}
+
+/// Fallible iterator over the `(key, value)` pairs in a CDB, returned by
+/// [`Reader::try_iter`](struct.Reader.html#method.try_iter).
+pub struct TryItemIterator<'a, 'file: 'a, F: Read + Seek + 'file> {
+    reader: &'a mut Reader<'file, F>,
+    /// Whether an error was already yielded.
+    done: bool,
+}
+
+impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for TryItemIterator<'a, 'file, F> {
+    /// A single `key`, `value` pair, or the error that ended the iteration.
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.reader.next_item() {
+            Ok(item) => item.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
             }
-            Err(_) => return None,
         }
-        // We're in the Footer/Hash Table of the file, no more items.
-        let mut buf: [u8; 8] = [0; 8];
-        {
-            let mut chunk = self.reader.file.take(8);
-            let _ = chunk.read(&mut buf);
+    }
+}
+
+/// Fallible iterator over the records in a CDB along with their position in
+/// the file, returned by
+/// [`Reader::record_positions`](struct.Reader.html#method.record_positions).
+pub struct PositionIterator<'a, 'file: 'a, F: Read + Seek + 'file> {
+    reader: &'a mut Reader<'file, F>,
+    /// Whether an error was already yielded.
+    done: bool,
+}
+
+impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for PositionIterator<'a, 'file, F> {
+    /// The position of a record, and its `key` and `value`, or the error that
+    /// ended the iteration.
+    type Item = Result<(u64, Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let item = self
+            .reader
+            .file
+            .stream_position()
+            .map_err(Error::from)
+            .and_then(|pos| Ok(self.reader.next_item()?.map(|(k, v)| (pos, k, v))));
+        match item {
+            Ok(item) => item.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
         }
-        let k = unpack([buf[0], buf[1], buf[2], buf[3]]); // Key length
-        let v = unpack([buf[4], buf[5], buf[6], buf[7]]); // Value length
+    }
+}
 
-        let mut key: Vec<u8> = vec![];
-        {
-            let mut chunk = self.reader.file.take(k as u64);
-            let _ = chunk.read_to_end(&mut key);
+/// Iterator over the `(key, value)` pairs in a CDB in ascending key order,
+/// returned by [`Reader::sorted_iter`](struct.Reader.html#method.sorted_iter).
+pub struct SortedIterator<'a, 'file: 'a, F: Read + Seek + 'file> {
+    reader: &'a mut Reader<'file, F>,
+    /// Sorted keys, alongside the position and length of their values.
+    entries: vec::IntoIter<(Vec<u8>, u64, u32)>,
+}
+
+impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for SortedIterator<'a, 'file, F> {
+    /// A single `key`, `value` pair.
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, pos, dlen) = self.entries.next()?;
+        let value = self.reader.read_value(pos, dlen).ok()?;
+        Some((key, value))
+    }
+}
+
+/// Iterator over the `(key, value)` pairs in a CDB that reads the records
+/// through a buffer, returned by
+/// [`Reader::scan_buffered`](struct.Reader.html#method.scan_buffered).
+pub struct BufferedItemIterator<'a, F: Read + Seek + 'a> {
+    file: BufReader<&'a mut F>,
+    /// Position in the file of the next record.
+    pos: u64,
+    table_start: u64,
+    endianness: Endianness,
+    format: Format,
+}
+
+impl<'a, F: Read + Seek + 'a> Iterator for BufferedItemIterator<'a, F> {
+    /// A single `key`, `value` pair.
+    type Item = (Vec<u8>, Vec<u8>);
+
+    /// Fetch the next (`key`, `value`) pair, if any, stopping early at a
+    /// corrupt record like [`ItemIterator`](struct.ItemIterator.html) does.
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.format.record_header_size() as u64;
+        if self.pos >= self.table_start || self.pos + header > self.table_start {
+            return None;
+        }
+        let mut buf = [0; 16];
+        let buf = &mut buf[..header as usize];
+        self.file.read_exact(buf).ok()?;
+        let (k, v) = self.format.unpack_pair(self.endianness, buf);
+        let (k, v) = (u32::try_from(k).ok()?, u32::try_from(v).ok()?);
+        let end = self.pos + header + u64::from(k) + u64::from(v);
+        if end > self.table_start {
+            return None;
         }
+        let mut key = vec![0; k as usize];
+        self.file.read_exact(&mut key).ok()?;
+        let mut value = vec![0; v as usize];
+        self.file.read_exact(&mut value).ok()?;
+        self.pos = end;
+        Some((key, value))
+    }
+}
 
-        let mut val: Vec<u8> = vec![];
-        {
-            let mut chunk = self.reader.file.take(v as u64);
-            let _ = chunk.read_to_end(&mut val);
+/// Iterator struct for the keys in a CDB, returned by
+/// [`Reader::key_iter`](struct.Reader.html#method.key_iter).
+pub struct KeyIterator<'a, 'file: 'a, F: Read + Seek + 'file> {
+    reader: &'a mut Reader<'file, F>,
+}
+
+/// Iterate over the keys in a CDB until the end of file.
+impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for KeyIterator<'a, 'file, F> {
+    /// A single `key`.
+    type Item = Vec<u8>;
+
+    /// Fetch the next `key`, if any, skipping over its value.
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.reader.next_record().ok()??;
+        let key = self.reader.read_chunk(k);
+        if self.reader.file.seek(SeekFrom::Current(i64::from(v))).is_err() {
+            return None;
         }
+        Some(key)
+    }
+}
 
-        Some((key, val))
+/// Iterator struct for the values in a CDB, returned by
+/// [`Reader::values_iter`](struct.Reader.html#method.values_iter).
+pub struct ValueIterator<'a, 'file: 'a, F: Read + Seek + 'file> {
+    reader: &'a mut Reader<'file, F>,
+}
+
+/// Iterate over the values in a CDB until the end of file.
+impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for ValueIterator<'a, 'file, F> {
+    /// A single `value`.
+    type Item = Vec<u8>;
+
+    /// Fetch the next `value`, if any, skipping over its key.
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.reader.next_record().ok()??;
+        if self.reader.file.seek(SeekFrom::Current(i64::from(k))).is_err() {
+            return None;
+        }
+        Some(self.reader.read_chunk(v))
     }
 }
 
@@ -129,7 +307,7 @@ impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for ItemIterator<'a, 'file,
 /// let len = cdb_reader.len();
 ///
 /// # let mut i = 0;
-/// for (k, v) in cdb_reader.into_iter() {
+/// for (k, v) in &mut cdb_reader {
 ///     // Consume the (k, v) pair.
 /// #    let _ = k;
 /// #    i += 1;
@@ -142,7 +320,7 @@ impl<'a, 'file: 'a, F: Read + Seek + 'file> Iterator for ItemIterator<'a, 'file,
 /// # // Do it again to make sure the iterator doesn't consume and lifetimes
 /// # // work as expected.
 /// # i = 0;
-/// # for (_, v) in cdb_reader.into_iter() {
+/// # for (_, v) in &mut cdb_reader {
 /// #     i += 1;
 /// #     let s = &i.to_string();
 /// #     let val = s.as_bytes();
@@ -159,48 +337,175 @@ impl<'a, 'file: 'a, F: Read + Seek + 'file> IntoIterator for &'a mut Reader<'fil
     type IntoIter = ItemIterator<'a, 'file, F>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let _ = self.file.seek(SeekFrom::Start(2048));
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
         ItemIterator { reader: self }
     }
 }
 
+/// Iterator over the `(key, value)` pairs in a CDB that owns the `Reader`,
+/// returned by its `into_iter`.
+pub struct IntoItemIterator<'file, F: Read + Seek + 'file> {
+    reader: Reader<'file, F>,
+}
+
+impl<'file, F: Read + Seek + 'file> Iterator for IntoItemIterator<'file, F> {
+    /// A single `key`, `value` pair.
+    type Item = (Vec<u8>, Vec<u8>);
+
+    /// Fetch the next (`key`, `value`) pair, if any, stopping early at a
+    /// corrupt record like [`ItemIterator`](struct.ItemIterator.html) does.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next_item().ok()?
+    }
+}
+
+/// Convert a [`Reader`]() CDB into an `Iterator` that consumes it, and the
+/// file if the `Reader` owns it.
+///
+/// ```
+/// use galvanize::Reader;
+///
+/// let cdb_reader = Reader::open("tests/testdata/top250pws.cdb").unwrap();
+/// let mut count = 0;
+/// for (_, v) in cdb_reader {
+///     count += 1;
+///     assert_eq!(v, count.to_string().into_bytes());
+/// }
+/// assert_eq!(count, 250);
+/// ```
+impl<'file, F: Read + Seek + 'file> IntoIterator for Reader<'file, F> {
+    /// A single `key`, `value` pair.
+    type Item = (Vec<u8>, Vec<u8>);
+
+    /// The [`IntoItemIterator`](struct.IntoItemIterator.html) type this will
+    /// convert into.
+    type IntoIter = IntoItemIterator<'file, F>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        IntoItemIterator { reader: self }
+    }
+}
+
+/// A `(key, value)` pair that may be borrowed from the reader's storage, as
+/// yielded by [`CdbRead::iter`](trait.CdbRead.html#tymethod.iter).
+pub type CowItem<'r> = (Cow<'r, [u8]>, Cow<'r, [u8]>);
+
+/// Iteration over the records of a CDB, regardless of how the reader accesses
+/// its storage.
+///
+/// Readers that have to copy the records out of their storage, like
+/// [`Reader`](struct.Reader.html), yield `Cow::Owned` pairs, while readers of
+/// in-memory CDBs like `SharedReader<&[u8]>` borrow the pairs from it. This
+/// allows writing code generic over the kind of reader:
+///
+/// ```
+/// use galvanize::reader::CdbRead;
+/// use galvanize::{Reader, SharedReader};
+/// use std::fs::{self, File};
+///
+/// fn total_len<R: CdbRead>(cdb_reader: &mut R) -> usize {
+///     cdb_reader.iter().map(|(k, v)| k.len() + v.len()).sum()
+/// }
+///
+/// let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+/// let bytes = fs::read("tests/testdata/top250pws.cdb").unwrap();
+/// let mut cdb_reader = Reader::new(&mut f).unwrap();
+/// let mut shared = SharedReader::new(&bytes[..]).unwrap();
+/// assert_eq!(total_len(&mut cdb_reader), total_len(&mut shared));
+/// ```
+pub trait CdbRead {
+    /// Iterate over all the `(key, value)` pairs, in the order they were
+    /// written, stopping at the first corrupt record.
+    fn iter<'r>(&'r mut self) -> Box<dyn Iterator<Item = CowItem<'r>> + 'r>;
+}
+
+impl<'a, F: Read + Seek + 'a> CdbRead for Reader<'a, F> {
+    fn iter<'r>(&'r mut self) -> Box<dyn Iterator<Item = CowItem<'r>> + 'r> {
+        Box::new(self.into_iter().map(|(k, v)| (Cow::Owned(k), Cow::Owned(v))))
+    }
+}
+
 impl<'a, F: Read + Seek + 'a> Reader<'a, F> {
     /// Creates a new `Reader` consuming the provided `file`.
     pub fn new(file: &'a mut F) -> Result<Reader<'a, F>> {
-        match file.seek(SeekFrom::End(0)) {
-            Err(e) => return Err(Error::IOError(e)),
-            Ok(n) => {
-                if n < 2048 {
-                    return Err(Error::CDBTooSmall);
-                }
-            }
-        };
+        Self::from_handle(Handle::Borrowed(file), Endianness::Little, Format::Cdb32)
+    }
 
-        // Using u32 instead of usize as standard CDBs can only be 4GB in size.
-        let mut index: Vec<(u32, u32)> = vec![];
-        let mut sum: u32 = 0;
+    /// Creates a new `Reader` consuming the provided `file`, whose positions
+    /// and lengths are stored with the width given by `format`.
+    pub fn new_with_format(file: &'a mut F, format: Format) -> Result<Reader<'a, F>> {
+        Self::from_handle(Handle::Borrowed(file), Endianness::Little, format)
+    }
 
-        let mut buf: Vec<u8> = vec![];
-        {
-            file.seek(SeekFrom::Start(0))?;
-            let mut chunk = file.take(2048);
-            chunk.read_to_end(&mut buf)?;
-        }
+    /// Creates a new `Reader` consuming the provided `file`, whose keys were
+    /// hashed with `hasher` instead of the DJB hash, for example by a
+    /// `Writer` created with `Writer::with_hasher`.
+    pub fn with_hasher(file: &'a mut F, hasher: &'static dyn Hasher) -> Result<Reader<'a, F>> {
+        let mut reader = Self::new(file)?;
+        reader.hasher = hasher;
+        Ok(reader)
+    }
+
+    /// Creates a new `Reader` for the `len` bytes long CDB embedded in
+    /// `file` starting at position `base`, for example after the header of a
+    /// container format.
+    ///
+    /// The positions stored in the CDB are relative to its own start, so the
+    /// CDB can be copied out of the `file` as is. Whatever the container has
+    /// after the `len` bytes isn't considered part of the CDB.
+    pub fn new_at(file: &'a mut F, base: u64, len: u64) -> Result<Reader<'a, Offset<&'a mut F>>> {
+        let file = Handle::Owned(Offset::new(file, base));
+        Reader::from_parts(file, Endianness::Little, Format::Cdb32, len)
+    }
 
-        for ix in 0..2048 / 8 {
-            let i = ix * 8;
-            let k = unpack([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
-            let v = unpack([buf[i + 4], buf[i + 5], buf[i + 6], buf[i + 7]]);
-            sum += v >> 1;
-            index.push((k, v));
+    /// Creates a new `Reader` consuming the provided `file`, whose 32-bit
+    /// quantities are stored using `endianness` instead of the little-endian
+    /// byte order mandated by the CDB specification.
+    pub fn with_endianness(file: &'a mut F, endianness: Endianness) -> Result<Reader<'a, F>> {
+        Self::from_handle(Handle::Borrowed(file), endianness, Format::Cdb32)
+    }
+
+    /// Creates a new `Reader` from either a borrowed or an owned `file`.
+    pub(crate) fn from_handle(
+        mut file: Handle<'a, F>,
+        endianness: Endianness,
+        format: Format,
+    ) -> Result<Reader<'a, F>> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        Self::from_parts(file, endianness, format, file_len)
+    }
+
+    /// Creates a new `Reader` from either a borrowed or an owned `file`,
+    /// whose size in bytes, `file_len`, is already known.
+    fn from_parts(
+        mut file: Handle<'a, F>,
+        endianness: Endianness,
+        format: Format,
+        file_len: u64,
+    ) -> Result<Reader<'a, F>> {
+        if file_len < format.header_size() as u64 {
+            return Err(Error::CDBTooSmall { size: file_len });
         }
-        let table_start = index.iter().map(|item| item.0).min().unwrap();
+
+        let mut buf = vec![0; format.header_size()];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buf)?;
+        let header = parse_header(&buf, endianness, format)?;
 
         Ok(Reader {
             file,
-            index,
-            table_start: table_start as usize,
-            length: sum as usize,
+            index: header.index,
+            table_start: header.table_start,
+            length: header.length,
+            file_len,
+            endianness,
+            format,
+            in_memory: 0,
+            probing: &LinearProbe,
+            hasher: &DjbHasher,
         })
     }
 
@@ -209,19 +514,114 @@ impl<'a, F: Read + Seek + 'a> Reader<'a, F> {
         self.length
     }
 
+    /// Use `probing` to look up keys instead of the standard linear probing,
+    /// to read CDB variants written with a different probe order. See
+    /// [`ProbeSequence`](../helpers/trait.ProbeSequence.html).
+    pub fn set_probing(&mut self, probing: &'static dyn ProbeSequence) {
+        self.probing = probing;
+    }
+
+    /// Use `hasher` to look up keys, carried over from a `Writer`.
+    pub(crate) fn set_hasher(&mut self, hasher: &'static dyn Hasher) {
+        self.hasher = hasher;
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    /// Size of this CDB in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.file_len
+    }
+
+    /// How the size of this CDB is split between the header, the records and
+    /// the hash tables footer.
+    pub fn size_breakdown(&self) -> SizeBreakdown {
+        SizeBreakdown {
+            header: self.records_start(),
+            records: self.table_start.saturating_sub(self.records_start()),
+            footer: self.file_len.saturating_sub(self.table_start),
+        }
+    }
+
+    /// Describe the hash table for `bucket`, reading it to find out how many of
+    /// its slots are occupied.
+    pub fn table_info(&mut self, bucket: u8) -> Result<TableInfo> {
+        let (position, slots) = self.index[bucket as usize];
+        let occupied = self.occupied_slots(bucket)?.len() as u32;
+        Ok(TableInfo {
+            position,
+            slots,
+            occupied,
+        })
+    }
+
+    /// Read every record in the hash table for `bucket`, in slot order.
+    ///
+    /// This exposes the second level of the CDB structure, which is useful to
+    /// find out which keys collide in an overloaded bucket.
+    pub fn bucket_records(&mut self, bucket: u8) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut records = vec![];
+        for (_, Pos(pos)) in self.occupied_slots(bucket)? {
+            self.file.seek(SeekFrom::Start(pos))?;
+            let (klen, dlen) = self.read_lengths(pos)?;
+            let mut key: Vec<u8> = vec![];
+            self.file.by_ref().take(u64::from(klen)).read_to_end(&mut key)?;
+            let mut value: Vec<u8> = vec![];
+            self.file.by_ref().take(u64::from(dlen)).read_to_end(&mut value)?;
+            records.push((key, value));
+        }
+        Ok(records)
+    }
+
+    /// The `(hash, position)` pairs of the slots in the hash table for
+    /// `bucket` that point to a record.
+    fn occupied_slots(&mut self, bucket: u8) -> Result<Vec<(Hash, Pos)>> {
+        let mut slots = self.table_slots(bucket)?;
+        // A slot is empty when it doesn't point to a record.
+        slots.retain(|&(_, pos)| pos != Pos(0));
+        Ok(slots)
+    }
+
+    /// The `(hash, position)` pairs of all the slots in the hash table for
+    /// `bucket`, in slot order.
+    fn table_slots(&mut self, bucket: u8) -> Result<Vec<(Hash, Pos)>> {
+        let (position, slots) = self.index[bucket as usize];
+        let slot_size = self.format.slot_size();
+        let mut buf: Vec<u8> = vec![];
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file
+            .by_ref()
+            .take(u64::from(slots) * slot_size as u64)
+            .read_to_end(&mut buf)?;
+        let (endianness, format) = (self.endianness, self.format);
+        Ok(buf
+            .chunks(slot_size)
+            .filter(|slot| slot.len() == slot_size)
+            .map(|slot| {
+                let (h, pos) = format.unpack_pair(endianness, slot);
+                (Hash(h as u32), Pos(pos))
+            })
+            .collect())
+    }
+
+    /// Estimate of how many bytes of memory this `Reader` holds.
+    ///
+    /// For every `Reader` this counts the `Reader` itself and its index of
+    /// the 256 hash tables, around 4KB. For a `Reader` created with
+    /// [`from_bytes`](#method.from_bytes) it also counts the whole CDB held in
+    /// memory. Those bytes are shared between clones, but every clone counts
+    /// them.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.index.capacity() * mem::size_of::<(u64, u32)>()
+            + self.in_memory
+    }
+
     /// Return a `Vec` of all the values under the given `key`.
     pub fn get(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
-        let mut i = 0;
-        let mut values: Vec<Vec<u8>> = vec![];
-        while let Ok(v) = self.get_from_pos(key, i) {
-            values.push(v);
-            i += 1;
-        }
-        values
+        self.get_values(key).map_while(|v| v.ok()).collect()
     }
 
     /// Return a `Vec` of all the keys in this Read Only CDB.
@@ -229,118 +629,1211 @@ impl<'a, F: Read + Seek + 'a> Reader<'a, F> {
     /// Keep in mind that if there're duplicated keys, they will appear
     /// multiple times in the resulting `Vec`.
     pub fn keys(&mut self) -> Vec<Vec<u8>> {
-        let mut keys: Vec<Vec<u8>> = vec![];
-        for item in self.into_iter() {
-            keys.push(item.0);
+        self.key_iter().collect()
+    }
+
+    /// Return a `Vec` of all the distinct keys in this Read Only CDB, in the
+    /// order they were first written.
+    ///
+    /// Unlike [`keys`](#method.keys), duplicated keys appear only once. This
+    /// needs to keep track of the keys already seen, so memory use is
+    /// O(distinct keys) on top of the result.
+    pub fn unique_keys(&mut self) -> Vec<Vec<u8>> {
+        let mut seen = HashSet::new();
+        self.key_iter().filter(|key| seen.insert(key.clone())).collect()
+    }
+
+    /// Return all the records in this Read Only CDB with the values grouped
+    /// by key, in the order they were written.
+    ///
+    /// Wrap the result in a [`CdbMap`](../types/struct.CdbMap.html) to
+    /// serialize it with the `serde` feature.
+    pub fn to_map(&mut self) -> BTreeMap<Vec<u8>, Vec<Vec<u8>>> {
+        let mut map: BTreeMap<Vec<u8>, Vec<Vec<u8>>> = BTreeMap::new();
+        for (key, value) in self {
+            map.entry(key).or_default().push(value);
+        }
+        map
+    }
+
+    /// Return a `Vec` of all the values in this Read Only CDB, in the order
+    /// they were written.
+    ///
+    /// Every value is returned, including all the values of duplicated keys.
+    pub fn values(&mut self) -> Vec<Vec<u8>> {
+        self.values_iter().collect()
+    }
+
+    /// Lazily iterate over all the values in this Read Only CDB, without
+    /// holding them all in memory at once.
+    ///
+    /// Keys are skipped over without being read.
+    pub fn values_iter(&mut self) -> ValueIterator<'_, 'a, F> {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        ValueIterator { reader: self }
+    }
+
+    /// Iterate over the last `count` `(key, value)` pairs in this CDB, or all
+    /// of them if there are fewer than `count`.
+    ///
+    /// The records before them are still read to skip over them, a CDB has
+    /// no way to find its last records directly.
+    pub fn tail(&mut self, count: usize) -> iter::Skip<ItemIterator<'_, 'a, F>> {
+        let skip = self.len().saturating_sub(count);
+        self.into_iter().skip(skip)
+    }
+
+    /// Lazily iterate over all the keys in this Read Only CDB.
+    ///
+    /// Values are skipped over without being read, which makes this much
+    /// cheaper than iterating over the `(key, value)` pairs for CDBs with
+    /// large values. Like with [`keys`](#method.keys), duplicated keys will
+    /// appear multiple times.
+    pub fn key_iter(&mut self) -> KeyIterator<'_, 'a, F> {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        KeyIterator { reader: self }
+    }
+
+    /// Call `f` with the key and value of every record in this CDB, stopping
+    /// at the first error.
+    ///
+    /// Unlike iterating over the `Reader`, records are read into a single
+    /// buffer that is reused, so scanning doesn't allocate per record. Errors
+    /// reading the CDB are converted into `E`, which allows `f` to return its
+    /// own errors.
+    pub fn for_each_record<E, C>(&mut self, mut f: C) -> result::Result<(), E>
+    where
+        E: From<Error>,
+        C: FnMut(&[u8], &[u8]) -> result::Result<(), E>,
+    {
+        let mut buf: Vec<u8> = vec![];
+        let start = self.records_start();
+        self.file.seek(SeekFrom::Start(start)).map_err(Error::from)?;
+        while let Some((k, v)) = self.next_record()? {
+            let position = self.file.stream_position().map_err(Error::from)?
+                - self.format.record_header_size() as u64;
+            let len = u64::from(k) + u64::from(v);
+            buf.clear();
+            self.file
+                .by_ref()
+                .take(len)
+                .read_to_end(&mut buf)
+                .map_err(Error::from)?;
+            if buf.len() as u64 != len {
+                return Err(Error::CorruptRecord { position }.into());
+            }
+            let (key, value) = buf.split_at(k as usize);
+            f(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Write every record in this CDB to `out` in the format used by
+    /// `cdbdump`, `+klen,dlen:key->value` per record, followed by an empty
+    /// line.
+    ///
+    /// Keys and values are written as is, so the output can be fed back to
+    /// `cdbmake` and other CDB tools as it is byte exact.
+    ///
+    /// ```
+    /// use galvanize::Reader;
+    ///
+    /// let mut cdb_reader = Reader::open("tests/testdata/top250pws.cdb").unwrap();
+    /// let mut out = vec![];
+    /// cdb_reader.dump(&mut out).unwrap();
+    /// assert!(out.starts_with(b"+6,1:123456->1\n+8,1:password->2\n"));
+    /// assert!(out.ends_with(b"\n\n"));
+    /// ```
+    pub fn dump<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        self.for_each_record(|key, value| write_cdbmake(out, key, value).map_err(Error::from))?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Whether any record in this CDB has the given `value`, under any key.
+    ///
+    /// CDBs are only indexed by key, so this is a linear scan over all the
+    /// records, O(n) in the size of the CDB. It stops at the first match, and
+    /// only reads the values with the same length as `value`. Records after
+    /// a read error or a corrupt record aren't checked.
+    pub fn contains_value(&mut self, value: &[u8]) -> bool {
+        let start = self.records_start();
+        if self.file.seek(SeekFrom::Start(start)).is_err() {
+            return false;
+        }
+        let mut buf: Vec<u8> = vec![];
+        while let Ok(Some((k, v))) = self.next_record() {
+            if v as usize != value.len() {
+                if self.file.seek(SeekFrom::Current(i64::from(k) + i64::from(v))).is_err() {
+                    return false;
+                }
+                continue;
+            }
+            buf.clear();
+            if self.file.seek(SeekFrom::Current(i64::from(k))).is_err()
+                || self.file.by_ref().take(u64::from(v)).read_to_end(&mut buf).is_err()
+            {
+                return false;
+            }
+            if buf == value {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Pick `n` `(key, value)` pairs uniformly at random from this CDB.
+    ///
+    /// This is a single full pass over the records using reservoir sampling,
+    /// so memory use is bounded by `n` regardless of the size of the CDB. The
+    /// same `seed` always yields the same sample from the same CDB. If there
+    /// are `n` records or fewer, all of them are returned.
+    pub fn sample(&mut self, n: usize, seed: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut state = seed;
+        let mut reservoir = Vec::with_capacity(n.min(self.len()));
+        for (i, item) in self.into_iter().enumerate() {
+            if i < n {
+                reservoir.push(item);
+            } else {
+                let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+                if j < n {
+                    reservoir[j] = item;
+                }
+            }
+        }
+        reservoir
+    }
+
+    /// Iterate over the `(key, value)` pairs in this CDB in ascending key
+    /// order, regardless of the order they were written in.
+    ///
+    /// The values under the same key are yielded in the order they were
+    /// written. This needs a pass over the records to collect and sort all
+    /// the keys before the first pair is yielded, so every key is held in
+    /// memory, along with 12 bytes for the position and length of its value.
+    /// Values are read as the iterator is advanced.
+    pub fn sorted_iter(&mut self) -> Result<SortedIterator<'_, 'a, F>> {
+        let mut entries = vec![];
+        let start = self.records_start();
+        self.file.seek(SeekFrom::Start(start))?;
+        while let Some((k, v)) = self.next_record()? {
+            let mut key: Vec<u8> = vec![];
+            self.file.by_ref().take(u64::from(k)).read_to_end(&mut key)?;
+            let pos = self.file.seek(SeekFrom::Current(i64::from(v)))? - u64::from(v);
+            entries.push((key, pos, v));
+        }
+        // The sort is stable, so values keep their order under each key.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(SortedIterator {
+            reader: self,
+            entries: entries.into_iter(),
+        })
+    }
+
+    /// Iterate over the records in this CDB along with the position in the
+    /// file where each of them starts, yielding an error when the file is
+    /// damaged like [`try_iter`](#method.try_iter) does.
+    ///
+    /// A position can be stored to later read the record directly, for
+    /// example with [`iter_from`](#method.iter_from).
+    pub fn record_positions(&mut self) -> PositionIterator<'_, 'a, F> {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        PositionIterator {
+            reader: self,
+            done: false,
+        }
+    }
+
+    /// Iterate over the `(key, value)` pairs in this CDB like `into_iter`
+    /// does, but reading the records section in 64KB chunks.
+    ///
+    /// Iterating with `into_iter` does a few small reads per record, which is
+    /// slow for a `File`, so prefer this to scan large CDBs. Like with
+    /// `into_iter`, the iteration stops early at a corrupt record.
+    pub fn scan_buffered(&mut self) -> BufferedItemIterator<'_, F> {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        BufferedItemIterator {
+            pos: start,
+            table_start: self.table_start,
+            endianness: self.endianness,
+            format: self.format,
+            file: BufReader::with_capacity(64 * 1024, &mut *self.file),
+        }
+    }
+
+    /// Iterate over the `(key, value)` pairs in this CDB, yielding an error
+    /// instead of stopping silently when the file is damaged.
+    ///
+    /// A record whose key and value lengths would extend into the hash tables
+    /// is reported as `Error::CorruptRecord`, after which the iteration ends.
+    pub fn try_iter(&mut self) -> TryItemIterator<'_, 'a, F> {
+        let start = self.records_start();
+        let _ = self.file.seek(SeekFrom::Start(start));
+        TryItemIterator {
+            reader: self,
+            done: false,
+        }
+    }
+
+    /// Iterate over the `(key, value)` pairs in this CDB, starting from the
+    /// record at `offset` instead of the first one.
+    ///
+    /// The `offset` must have been obtained from
+    /// [`ItemIterator::offset`](struct.ItemIterator.html#method.offset), as
+    /// there's no way to tell whether an arbitrary position is the start of a
+    /// record. If `offset` is outside of the records section,
+    /// `Error::InvalidOffset` is returned.
+    pub fn iter_from(&mut self, offset: u64) -> Result<ItemIterator<'_, 'a, F>> {
+        if offset < self.records_start() || offset > self.table_start {
+            return Err(Error::InvalidOffset { offset });
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        Ok(ItemIterator { reader: self })
+    }
+
+    /// Check the structural integrity of this CDB.
+    ///
+    /// Every hash table must be within the bounds of the file, and every
+    /// record must fit before the start of the hash tables. Overlapping hash
+    /// tables are already rejected when creating the `Reader`. Every record
+    /// must also be pointed at by a slot of the hash table its key belongs
+    /// to, holding the key's hash, otherwise lookups can't find it, and every
+    /// slot must point at a record.
+    ///
+    /// This reads every key and every slot, and keeps track of the slots in
+    /// memory. Any problem found is listed in the returned report, an `Err`
+    /// is only returned if the `file` couldn't be read.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        let mut report = VerifyReport {
+            total_bytes: self.file_len,
+            ..VerifyReport::default()
+        };
+
+        // Which bucket and hash each occupied slot points at a record with.
+        let mut pointed: HashMap<u64, (usize, u64)> = HashMap::new();
+        let slot_size = self.format.slot_size() as u64;
+        for (bucket, &(position, slots)) in self.index.clone().iter().enumerate() {
+            if (position, slots) == (0, 0) {
+                // An empty table that doesn't point anywhere.
+                continue;
+            }
+            let end = position.saturating_add(u64::from(slots) * slot_size);
+            if position < self.records_start() || end > report.total_bytes {
+                report.anomalies.push(Anomaly::TableOutOfBounds {
+                    bucket,
+                    position,
+                    slots,
+                });
+                continue;
+            }
+            self.file.seek(SeekFrom::Start(position))?;
+            for _ in 0..slots {
+                let (h, rec_pos) = self.read_pair()?;
+                if rec_pos != 0 {
+                    pointed.insert(rec_pos, (bucket, h));
+                }
+            }
+        }
+
+        let table_start = self.table_start;
+        let record_header_size = self.format.record_header_size() as u64;
+        let mut pos = self.records_start();
+        let mut walked = true;
+        while pos < table_start {
+            if pos + record_header_size > table_start {
+                report
+                    .anomalies
+                    .push(Anomaly::RecordOutOfBounds { position: pos });
+                walked = false;
+                break;
+            }
+            self.file.seek(SeekFrom::Start(pos))?;
+            let (klen, dlen) = self.read_pair()?;
+
+            let next = (pos + record_header_size)
+                .saturating_add(klen)
+                .saturating_add(dlen);
+            if next > table_start {
+                report
+                    .anomalies
+                    .push(Anomaly::RecordOutOfBounds { position: pos });
+                walked = false;
+                break;
+            }
+            let mut key = vec![0; klen as usize];
+            self.file.read_exact(&mut key)?;
+            let h = Hash(self.hasher.hash(&key));
+            if pointed.remove(&pos) != Some((h.bucket(), u64::from(h.0))) {
+                report
+                    .anomalies
+                    .push(Anomaly::UnreachableRecord { position: pos });
+            }
+            report.records += 1;
+            pos = next;
+        }
+
+        // Slots left point somewhere other than the start of a record. They
+        // are only reported if all the records could be walked, as otherwise
+        // they're likely to point at the records that couldn't be.
+        if walked {
+            let mut dangling: Vec<(usize, u64)> = pointed
+                .into_iter()
+                .map(|(position, (bucket, _))| (bucket, position))
+                .collect();
+            dangling.sort_unstable();
+            for (bucket, position) in dangling {
+                report
+                    .anomalies
+                    .push(Anomaly::DanglingSlot { bucket, position });
+            }
+        }
+
+        if report.records != self.length {
+            report.anomalies.push(Anomaly::RecordCountMismatch {
+                records: report.records,
+                expected: self.length,
+            });
         }
-        keys
+        Ok(report)
+    }
+
+    /// Return a `Vec` of all the values under the given `key`, found by
+    /// scanning every record instead of using the hash table.
+    ///
+    /// This is O(n) on the number of records and much slower than
+    /// [`get`](#method.get). It is meant as a last resort to salvage data
+    /// from a CDB whose hash table footer is corrupt, while the records
+    /// themselves are intact.
+    pub fn find_linear(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        self.into_iter()
+            .filter(|item| item.0 == key)
+            .map(|item| item.1)
+            .collect()
     }
 
     /// Pull the `value` bytes for the first occurence of the given `key` in
     /// this CDB.
+    ///
+    /// A missing `key` is returned as `Error::KeyNotInCDB`, prefer
+    /// [`lookup`](#method.lookup) to tell it apart from actual errors.
     pub fn get_first(&mut self, key: &[u8]) -> Result<Vec<u8>> {
         self.get_from_pos(key, 0)
     }
 
+    /// Pull the `value` bytes for the first occurence of the given `key` in
+    /// this CDB, or `None` if the `key` isn't in it.
+    ///
+    /// Errors are only returned for IO errors and corrupt records.
+    ///
+    /// ```
+    /// use galvanize::Reader;
+    ///
+    /// let mut cdb_reader = Reader::open("tests/testdata/top250pws.cdb").unwrap();
+    /// assert_eq!(cdb_reader.lookup(b"password").unwrap(), Some(b"2".to_vec()));
+    /// assert_eq!(cdb_reader.lookup(b"not there").unwrap(), None);
+    /// ```
+    pub fn lookup(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        match self.next_match(key, &mut probe)? {
+            Some((pos, dlen)) => Ok(Some(self.read_value(pos, dlen)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Pull the `value` bytes for the first occurence of the given `key`,
+    /// along with how many values there are under the `key` in total.
+    ///
+    /// Unlike [`get_first`](#method.get_first), this keeps probing the
+    /// `key`'s bucket after the first match to count the rest of them, but
+    /// only the first value is read.
+    pub fn get_first_with_count(&mut self, key: &[u8]) -> Result<(Vec<u8>, u32)> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        let (pos, dlen) = match self.next_match(key, &mut probe)? {
+            Some(found) => found,
+            None => return Err(Error::KeyNotInCDB),
+        };
+        let mut count = 1;
+        while self.next_match(key, &mut probe)?.is_some() {
+            count += 1;
+        }
+        Ok((self.read_value(pos, dlen)?, count))
+    }
+
+    /// Pull the `value` bytes for the last occurence of the given `key` in
+    /// this CDB, the one written most recently.
+    ///
+    /// The `key`'s bucket is probed once, reading only the value of the last
+    /// match. If the `key` isn't in the CDB, `Error::KeyNotInCDB` is
+    /// returned.
+    pub fn get_last(&mut self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        let mut last = None;
+        while let Some(found) = self.next_match(key, &mut probe)? {
+            last = Some(found);
+        }
+        match last {
+            Some((pos, dlen)) => self.read_value(pos, dlen),
+            None => Err(Error::KeyNotInCDB),
+        }
+    }
+
+    /// Whether there's any value stored under `key`.
+    ///
+    /// Only the keys of the records probed are read, never their values. A
+    /// missing key is `Ok(false)`, errors are only returned for IO errors.
+    pub fn contains_key(&mut self, key: &[u8]) -> Result<bool> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        Ok(self.next_match(key, &mut probe)?.is_some())
+    }
+
+    /// Length in bytes of the first value stored under `key`, without reading
+    /// the value itself.
+    ///
+    /// If the `key` isn't in the CDB, `Error::KeyNotInCDB` is returned.
+    pub fn value_len(&mut self, key: &[u8]) -> Result<u32> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        match self.next_match(key, &mut probe)? {
+            Some((_, dlen)) => Ok(dlen),
+            None => Err(Error::KeyNotInCDB),
+        }
+    }
+
+    /// Find where the first value stored under `key` is, without reading it.
+    ///
+    /// If the `key` isn't in the CDB, `Error::KeyNotInCDB` is returned. The
+    /// value can then be read with [`read_handle`](#method.read_handle), as
+    /// long as the CDB isn't replaced in the meantime.
+    pub fn locate(&mut self, key: &[u8]) -> Result<ValueHandle> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        match self.next_match(key, &mut probe)? {
+            Some((pos, dlen)) => Ok(ValueHandle {
+                offset: pos,
+                len: dlen,
+            }),
+            None => Err(Error::KeyNotInCDB),
+        }
+    }
+
+    /// Read the value at `handle`, as returned by [`locate`](#method.locate).
+    ///
+    /// If the `handle` points past the records, `Error::InvalidOffset` is
+    /// returned.
+    pub fn read_handle(&mut self, handle: ValueHandle) -> Result<Vec<u8>> {
+        let end = handle.offset.saturating_add(u64::from(handle.len));
+        if handle.offset < self.records_start() || end > self.table_start {
+            return Err(Error::InvalidOffset {
+                offset: handle.offset,
+            });
+        }
+        self.read_value(handle.offset, handle.len)
+    }
+
+    /// Pull the `value` bytes for the first occurence of the given `key` into
+    /// a buffer on the stack, without allocating.
+    ///
+    /// This is meant for lookups of values known to be tiny. If the value is
+    /// longer than `N` bytes, `Error::EntryTooLarge` is returned.
+    #[cfg(feature = "arrayvec")]
+    pub fn get_first_small<const N: usize>(&mut self, key: &[u8]) -> Result<ArrayVec<u8, N>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        let (pos, dlen) = match self.next_match(key, &mut probe)? {
+            Some(found) => found,
+            None => return Err(Error::KeyNotInCDB),
+        };
+        let len = dlen as usize;
+        if len > N {
+            return Err(Error::EntryTooLarge {
+                size: dlen,
+                capacity: N,
+            });
+        }
+        let mut buf = [0; N];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut buf[..len])?;
+        let mut value = ArrayVec::from(buf);
+        value.truncate(len);
+        Ok(value)
+    }
+
+    /// Pull the `value` bytes for the first occurence of the given `key`,
+    /// probing at most `max_probes` hash table slots.
+    ///
+    /// If the `key` or an empty slot isn't found within `max_probes` slots,
+    /// `Error::ProbeBudgetExceeded` is returned. Exceeding the budget is not
+    /// the same as the `key` not being in the CDB, it just means that it
+    /// wasn't possible to find out within the allowed amount of work. This
+    /// protects against pathological buckets in adversarially constructed
+    /// databases, databases with a good distribution never hit the budget.
+    pub fn get_first_bounded(&mut self, key: &[u8], max_probes: usize) -> Result<Vec<u8>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        probe.budget = max_probes.min(u32::MAX as usize) as u32;
+        match self.next_match(key, &mut probe)? {
+            Some((pos, dlen)) => self.read_value(pos, dlen),
+            None if probe.over_budget => Err(Error::ProbeBudgetExceeded),
+            None => Err(Error::KeyNotInCDB),
+        }
+    }
+
+    /// Look up the first occurence of each of the `keys`, and aggregate how
+    /// much probing was needed.
+    ///
+    /// This quantifies how close this CDB gets to cdb's promise of two disk
+    /// accesses for a successful lookup and one for an unsuccessful one, for
+    /// a given access pattern. Values aren't read.
+    pub fn profile_lookups(&mut self, keys: &[&[u8]]) -> Result<LookupProfile> {
+        let mut probes: Vec<u32> = Vec::with_capacity(keys.len());
+        let mut over_two = 0;
+        for key in keys {
+            let h = self.hasher.hash(key);
+            let mut probe = Probe::new(&self.index, h, self.format, self.probing);
+            self.next_match(key, &mut probe)?;
+            probes.push(probe.probed);
+            // Every slot and every record read is a disk access.
+            if probe.probed + probe.records > 2 {
+                over_two += 1;
+            }
+        }
+        if probes.is_empty() {
+            return Ok(LookupProfile::default());
+        }
+
+        probes.sort_unstable();
+        let p99 = (probes.len() * 99).div_ceil(100) - 1;
+        Ok(LookupProfile {
+            lookups: probes.len(),
+            min_probes: probes[0],
+            max_probes: probes[probes.len() - 1],
+            mean_probes: probes.iter().map(|&p| f64::from(p)).sum::<f64>() / probes.len() as f64,
+            p99_probes: probes[p99],
+            over_two_accesses: f64::from(over_two) / probes.len() as f64,
+        })
+    }
+
+    /// Read all the hash tables to find how full each of them is and how far
+    /// their records landed from the first slot probed for their key.
+    ///
+    /// A large probe distance means some keys pile up in a few buckets, so
+    /// their lookups read many slots. Records aren't read.
+    pub fn stats(&mut self) -> Result<Stats> {
+        let mut stats = Stats::default();
+        for bucket in 0..=255u8 {
+            let slots = self.table_slots(bucket)?;
+            let nslots = slots.len() as u32;
+            let mut table = TableStats {
+                slots: nslots,
+                ..TableStats::default()
+            };
+            for (i, &(h, pos)) in slots.iter().enumerate() {
+                if pos == Pos(0) {
+                    continue;
+                }
+                table.occupied += 1;
+                let first = h.slot(nslots);
+                let distance = (0..nslots)
+                    .find(|&attempt| self.probing.slot(first, attempt, nslots) == i as u32)
+                    .unwrap_or(nslots);
+                table.max_probe_distance = table.max_probe_distance.max(distance);
+            }
+            if nslots > 0 {
+                table.fill_ratio = f64::from(table.occupied) / f64::from(nslots);
+            }
+            stats.records += table.occupied as usize;
+            stats.max_probe_distance = stats.max_probe_distance.max(table.max_probe_distance);
+            stats.tables.push(table);
+        }
+        Ok(stats)
+    }
+
+    /// How many values are stored under the given `key`.
+    ///
+    /// Only the `key`'s bucket is probed and the values aren't read, so this
+    /// is cheaper than `get(key).len()`.
+    pub fn count(&mut self, key: &[u8]) -> Result<usize> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        let mut count = 0;
+        while self.next_match(key, &mut probe)?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// How many values are stored under the given `key`, same as
+    /// [`count`](#method.count).
+    #[inline]
+    pub fn count_values(&mut self, key: &[u8]) -> Result<usize> {
+        self.count(key)
+    }
+
     /// Pull the `value` bytes for the `index`st occurence of the given `key`
     /// in this CDB.
+    ///
+    /// If the `key` isn't in the CDB, `Error::KeyNotInCDB` is returned. If it
+    /// is, but it has `index` values or fewer, `Error::OccurrenceOutOfRange`
+    /// is returned instead. A hash table slot pointing at a record that
+    /// doesn't fit in the records section is `Error::CorruptRecord`.
+    ///
+    /// [`count`](#method.count) finds how many values there are without
+    /// reading them, and [`get_last`](#method.get_last) reads the last one.
     pub fn get_from_pos(&mut self, key: &[u8], index: u32) -> Result<Vec<u8>> {
-        let h = hash(key);
-        let (start, nslots) = self.index[(h & 0xff) as usize];
-
-        if nslots > index {
-            // Bucket has keys.
-            let end = start + (nslots << 3);
-            let slot_off = start + (((h >> 8) % nslots) << 3);
-
-            let mut counter = 0;
-            // Every 8 bytes from the slot offset to the end, and then from the
-            // end to the slot_offset.
-            for pos in (slot_off..end)
-                .chain(start..slot_off)
-                .enumerate()
-                .filter(|item| item.0 % 8 == 0)
-                .map(|item| item.1)
-            {
-                let mut buf: [u8; 8] = [0; 8];
-                {
-                    self.file.seek(SeekFrom::Start(pos as u64))?;
-                    let mut chunk = self.file.take(8);
-                    chunk.read_exact(&mut buf)?;
+        let mut buf = vec![];
+        self.get_into(key, index, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the `value` bytes for the `index`st occurence of the given `key`
+    /// into `buf`, returning how many bytes were read.
+    ///
+    /// `buf` is cleared first, but its allocation is reused, so a single
+    /// buffer can be used for many lookups. Errors are the same as for
+    /// [`get_from_pos`](#method.get_from_pos).
+    pub fn get_into(&mut self, key: &[u8], index: u32, buf: &mut Vec<u8>) -> Result<usize> {
+        buf.clear();
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        if probe.nslots == 0 {
+            return Err(Error::KeyNotInCDB);
+        }
+
+        let mut counter = 0;
+        while let Some((pos, dlen)) = self.next_match(key, &mut probe)? {
+            if counter == index {
+                return self.read_value_into(pos, dlen, buf);
+            }
+            counter += 1;
+        }
+        if counter == 0 {
+            Err(Error::KeyNotInCDB)
+        } else {
+            Err(Error::OccurrenceOutOfRange { available: counter })
+        }
+    }
+
+    /// Lazily iterate over all the values under the given `key`.
+    ///
+    /// Unlike [`get`](#method.get), values are only read from the `file` as
+    /// the iterator is advanced, so `get_values(key).nth(3)` doesn't read
+    /// the first three values. IO errors are yielded as `Err` items.
+    pub fn get_values<'r>(
+        &'r mut self,
+        key: &'r [u8],
+    ) -> ValueIter<'r, 'a, F> {
+        let probe = Probe::new(&self.index, self.hasher.hash(key), self.format, self.probing);
+        ValueIter {
+            reader: self,
+            key,
+            probe,
+            done: false,
+        }
+    }
+
+    /// Lazily iterate over all the values under the given `key`, same as
+    /// [`get_values`](#method.get_values).
+    #[inline]
+    pub fn get_iter<'r>(&'r mut self, key: &'r [u8]) -> ValueIter<'r, 'a, F> {
+        self.get_values(key)
+    }
+
+    /// Read the key and value lengths of the record at the current position
+    /// of the `file`, if it's before the hash tables.
+    ///
+    /// If the record extends into the hash tables, `Error::CorruptRecord` is
+    /// returned instead of letting the caller read the footer as data.
+    fn next_record(&mut self) -> Result<Option<(u32, u32)>> {
+        let pos = self.file.stream_position()?;
+        let table_start = self.table_start;
+        if pos >= table_start {
+            // We're in the Footer/Hash Table of the file, no more items.
+            return Ok(None);
+        }
+        let corrupt = Error::CorruptRecord { position: pos };
+        let header = self.format.record_header_size() as u64;
+        if pos + header > table_start {
+            return Err(corrupt);
+        }
+        let (k, v) = self.read_lengths(pos)?; // Key and value lengths
+        if pos + header + u64::from(k) + u64::from(v) > table_start {
+            return Err(corrupt);
+        }
+        Ok(Some((k, v)))
+    }
+
+    /// Read the `(key, value)` pair of the record at the current position of
+    /// the `file`, if it's before the hash tables.
+    fn next_item(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let (k, v) = match self.next_record()? {
+            Some(lengths) => lengths,
+            None => return Ok(None),
+        };
+        let mut key: Vec<u8> = vec![];
+        self.file.by_ref().take(u64::from(k)).read_to_end(&mut key)?;
+        let mut val: Vec<u8> = vec![];
+        self.file.by_ref().take(u64::from(v)).read_to_end(&mut val)?;
+        Ok(Some((key, val)))
+    }
+
+    /// Read up to `len` bytes from the current position of the `file`.
+    fn read_chunk(&mut self, len: u32) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![];
+        {
+            let mut chunk = self.file.by_ref().take(u64::from(len));
+            let _ = chunk.read_to_end(&mut buf);
+        }
+        buf
+    }
+
+    /// Find the next record in the `probe`d bucket whose key is `key`,
+    /// returning the position and length of its value.
+    ///
+    /// The `file` is left positioned at the start of the value.
+    fn next_match(&mut self, key: &[u8], probe: &mut Probe) -> Result<Option<(u64, u32)>> {
+        while let Some(pos) = probe.next_slot() {
+            self.file.seek(SeekFrom::Start(pos))?;
+            let (rec_h, rec_pos) = self.read_pair()?;
+
+            if rec_pos == 0 {
+                // Empty slot, key not in file.
+                return Ok(None);
+            } else if rec_h == u64::from(probe.h.0) {
+                // Hash of key found in file.
+                probe.records += 1;
+                // The slot must point at a record that fits in the records
+                // section, otherwise the file is damaged.
+                let corrupt = Error::CorruptRecord { position: rec_pos };
+                let key_pos = rec_pos + self.format.record_header_size() as u64;
+                if rec_pos < self.records_start() || key_pos > self.table_start {
+                    return Err(corrupt);
+                }
+                let (klen, dlen) = self.read_lengths(rec_pos)?;
+                if key_pos + u64::from(klen) + u64::from(dlen) > self.table_start {
+                    return Err(corrupt);
                 }
-                let rec_h = unpack([buf[0], buf[1], buf[2], buf[3]]);
-                let rec_pos = unpack([buf[4], buf[5], buf[6], buf[7]]);
-
-                if rec_h == 0 {
-                    // Key not in file.
-                    return Err(Error::KeyNotInCDB);
-                } else if rec_h == h {
-                    // Hash of key found in file.
-                    {
-                        self.file.seek(SeekFrom::Start(rec_pos as u64))?;
-                        let mut chunk = self.file.take(8);
-                        chunk.read_exact(&mut buf)?;
-                    }
-                    let klen = unpack([buf[0], buf[1], buf[2], buf[3]]);
-                    let dlen = unpack([buf[4], buf[5], buf[6], buf[7]]);
 
-                    let mut buf: Vec<u8> = vec![];
-                    {
-                        let mut chunk = self.file.take(klen as u64);
-                        chunk.read_to_end(&mut buf)?;
-                    }
-                    {
-                        if buf == key {
-                            // Found key in file
-                            buf.clear();
-
-                            let mut chunk = self.file.take(dlen as u64);
-                            chunk.read_to_end(&mut buf)?;
-
-                            if counter == index {
-                                return Ok(buf);
-                            }
-                            counter += 1;
-                        }
-                    }
+                let mut rec_key: Vec<u8> = vec![];
+                {
+                    let mut chunk = self.file.by_ref().take(u64::from(klen));
+                    chunk.read_to_end(&mut rec_key)?;
+                }
+                if rec_key == key {
+                    // Found key in file
+                    return Ok(Some((key_pos + u64::from(klen), dlen)));
                 }
             }
         }
-        Err(Error::KeyNotInCDB)
+        Ok(None)
+    }
+
+    /// Read the key of the record stored at `pos`.
+    pub(crate) fn key_at(&mut self, pos: u64) -> Result<Vec<u8>> {
+        let (klen, _) = self.read_lengths(pos)?;
+        self.read_value(pos + self.format.record_header_size() as u64, klen)
+    }
+
+    /// Position in the file where the records start, right after the header.
+    fn records_start(&self) -> u64 {
+        self.format.header_size() as u64
+    }
+
+    /// Read a pair of numbers, a hash table slot or a record header, at the
+    /// current position of the `file`.
+    fn read_pair(&mut self) -> Result<(u64, u64)> {
+        let mut buf = [0; 16];
+        let buf = &mut buf[..self.format.pair_size()];
+        self.file.read_exact(buf)?;
+        Ok(self.format.unpack_pair(self.endianness, buf))
+    }
+
+    /// Read the key and value lengths of the record stored at `pos`, leaving
+    /// the `file` positioned at the start of its key.
+    ///
+    /// Lengths that don't fit in 32 bits, only possible in `Format::Cdb64`
+    /// files, are reported as `Error::CorruptRecord`.
+    fn read_lengths(&mut self, pos: u64) -> Result<(u32, u32)> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        let (klen, dlen) = self.read_pair()?;
+        match (u32::try_from(klen), u32::try_from(dlen)) {
+            (Ok(klen), Ok(dlen)) => Ok((klen, dlen)),
+            _ => Err(Error::CorruptRecord { position: pos }),
+        }
+    }
+
+    /// Read the `dlen` bytes long value stored at `pos`.
+    fn read_value(&mut self, pos: u64, dlen: u32) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = vec![];
+        self.read_value_into(pos, dlen, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Append the `dlen` bytes long value stored at `pos` to `buf`, returning
+    /// how many bytes were read.
+    fn read_value_into(&mut self, pos: u64, dlen: u32, buf: &mut Vec<u8>) -> Result<usize> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = self.file.by_ref().take(u64::from(dlen));
+        Ok(chunk.read_to_end(buf)?)
+    }
+}
+
+/// The parsed contents of a CDB header.
+pub(crate) struct Header {
+    /// Position and number of slots of each of the 256 hash tables.
+    pub(crate) index: Vec<(u64, u32)>,
+    /// Position where the hash tables start.
+    pub(crate) table_start: u64,
+    /// How many records there are.
+    pub(crate) length: usize,
+}
+
+/// Read the 256 hash table pointers out of the CDB header in `buf`, checking
+/// that the tables are laid out after the records, back to back.
+///
+pub(crate) fn parse_header(buf: &[u8], endianness: Endianness, format: Format) -> Result<Header> {
+    let mut index: Vec<(u64, u32)> = vec![];
+    let mut sum: u64 = 0;
+
+    for (ix, pair) in buf.chunks(format.pair_size()).enumerate() {
+        let (k, v) = format.unpack_pair(endianness, pair);
+        // Tables can't have more slots than the 32 bit hashes can address.
+        let v = u32::try_from(v).map_err(|_| Error::CorruptTable { bucket: ix })?;
+        if (k, v) != (0, 0) && k < format.header_size() as u64 {
+            // The records, and therefore the tables, start after the header.
+            return Err(Error::CorruptTable { bucket: ix });
+        }
+        sum += u64::from(v >> 1);
+        index.push((k, v));
+    }
+    // An all zero pointer is an empty table that doesn't say where the tables
+    // start, like in a header only CDB with no records at all.
+    let table_start = index
+        .iter()
+        .filter(|&&item| item != (0, 0))
+        .map(|item| item.0)
+        .min()
+        .unwrap_or(format.header_size() as u64);
+
+    // The non-empty hash tables must follow the records back to back,
+    // otherwise they overlap with each other or with the records.
+    let mut tables: Vec<(u64, u32, usize)> = index
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(_, nslots))| nslots > 0)
+        .map(|(bucket, &(pos, nslots))| (pos, nslots, bucket))
+        .collect();
+    tables.sort_unstable();
+    let mut expected = table_start;
+    for (pos, nslots, bucket) in tables {
+        if pos != expected {
+            return Err(Error::CorruptTable { bucket });
+        }
+        expected += u64::from(nslots) * format.slot_size() as u64;
+    }
+    Ok(Header {
+        index,
+        table_start,
+        length: sum as usize,
+    })
+}
+
+/// Advance the pseudo random number generator `state`, returning the next
+/// number in the sequence. This is SplitMix64, which is plenty for sampling.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Walks the hash table slots of the bucket a hash belongs to, starting at
+/// the hash's slot and wrapping around to the start of the table.
+#[derive(Debug)]
+pub(crate) struct Probe {
+    /// Hash of the key being looked up.
+    pub(crate) h: Hash,
+    /// Position in the file where the bucket's hash table starts.
+    start: u64,
+    /// Size in bytes of each slot of the hash table.
+    slot_size: u64,
+    /// How many slots are there in the bucket's hash table.
+    pub(crate) nslots: u32,
+    /// Slot where the probing started.
+    first: u32,
+    /// How many slots have been probed so far.
+    probed: u32,
+    /// How many records with a matching hash have been read so far.
+    records: u32,
+    /// How many slots can be probed at most.
+    budget: u32,
+    /// Whether the probing stopped because it ran out of `budget`.
+    over_budget: bool,
+    /// Order in which the slots are probed.
+    probing: &'static dyn ProbeSequence,
+}
+
+impl Probe {
+    pub(crate) fn new(
+        index: &[(u64, u32)],
+        h: u32,
+        format: Format,
+        probing: &'static dyn ProbeSequence,
+    ) -> Probe {
+        let h = Hash(h);
+        let (start, nslots) = index[h.bucket()];
+        // An empty table has no slot to start probing at.
+        let first = if nslots == 0 { 0 } else { h.slot(nslots) };
+        Probe {
+            h,
+            start,
+            slot_size: format.slot_size() as u64,
+            nslots,
+            first,
+            probed: 0,
+            records: 0,
+            budget: u32::MAX,
+            over_budget: false,
+            probing,
+        }
+    }
+
+    /// Position in the file of the next slot to probe, if any are left.
+    pub(crate) fn next_slot(&mut self) -> Option<u64> {
+        if self.probed >= self.nslots {
+            return None;
+        }
+        if self.probed >= self.budget {
+            self.over_budget = true;
+            return None;
+        }
+        let slot = self.probing.slot(self.first, self.probed, self.nslots);
+        self.probed += 1;
+        Some(self.start + u64::from(slot) * self.slot_size)
+    }
+}
+
+/// Iterator over the values stored under a single key in a CDB, returned by
+/// [`Reader::get_values`](struct.Reader.html#method.get_values).
+pub struct ValueIter<'r, 'file: 'r, F: Read + Seek + 'file> {
+    reader: &'r mut Reader<'file, F>,
+    key: &'r [u8],
+    probe: Probe,
+    /// Whether the probe finished, either on an empty slot or on an error.
+    done: bool,
+}
+
+impl<'r, 'file: 'r, F: Read + Seek + 'file> Iterator for ValueIter<'r, 'file, F> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let found = self.reader.next_match(self.key, &mut self.probe);
+        let value = match found {
+            Ok(Some((pos, dlen))) => self.reader.read_value(pos, dlen),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+        if value.is_err() {
+            self.done = true;
+        }
+        Some(value)
+    }
+}
+
+impl Reader<'static, Cursor<Arc<[u8]>>> {
+    /// Creates a new `Reader` over an in-memory CDB.
+    ///
+    /// The `bytes` are kept behind an `Arc`, so cloning the resulting `Reader`
+    /// is cheap: every clone shares the same bytes, while having its own read
+    /// position.
+    pub fn from_bytes<B: Into<Arc<[u8]>>>(bytes: B) -> Result<Self> {
+        let bytes = bytes.into();
+        let len = bytes.len();
+        let handle = Handle::Owned(Cursor::new(bytes));
+        let mut reader = Self::from_handle(handle, Endianness::Little, Format::Cdb32)?;
+        reader.in_memory = len;
+        Ok(reader)
+    }
+}
+
+impl<'a> Reader<'a, Cursor<&'a [u8]>> {
+    /// Creates a new `Reader` over an in-memory CDB borrowed from `bytes`,
+    /// without copying them.
+    pub fn from_slice(bytes: &'a [u8]) -> Result<Self> {
+        Self::from_handle(Handle::Owned(Cursor::new(bytes)), Endianness::Little, Format::Cdb32)
+    }
+}
+
+/// Parse a `Reader` from an in-memory CDB, performing the same validation as
+/// [`Reader::new`](struct.Reader.html#method.new).
+///
+/// #Example
+///
+/// ```
+/// # use galvanize::Result;
+/// use galvanize::Reader;
+/// use std::convert::TryFrom;
+/// # use std::fs::File;
+/// # use std::io::Read;
+///
+/// # fn main() {
+/// #     do_try().unwrap();
+/// # }
+/// #
+/// # fn do_try() -> Result<()> {
+/// # let mut bytes = vec![];
+/// # File::open("tests/testdata/top250pws.cdb")?.read_to_end(&mut bytes)?;
+/// let mut cdb_reader = Reader::try_from(&bytes[..])?;
+/// assert_eq!(cdb_reader.get_first("letmein".as_bytes())?, "10".as_bytes());
+/// #
+/// #     Ok(())
+/// # }
+/// ```
+impl<'a> TryFrom<&'a [u8]> for Reader<'a, Cursor<&'a [u8]>> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        Reader::from_slice(bytes)
+    }
+}
+
+/// Cloning a `Reader` gives the clone its own copy of the underlying `file`,
+/// for example a `Cursor` over the same shared bytes for readers created with
+/// [`Reader::from_bytes`](#method.from_bytes), so that each copy can be used
+/// independently.
+impl<'a, F: Read + Seek + Clone + 'a> Clone for Reader<'a, F> {
+    fn clone(&self) -> Self {
+        Reader {
+            file: Handle::Owned((*self.file).clone()),
+            index: self.index.clone(),
+            table_start: self.table_start,
+            length: self.length,
+            file_len: self.file_len,
+            endianness: self.endianness,
+            in_memory: self.in_memory,
+            probing: self.probing,
+            hasher: self.hasher,
+            format: self.format,
+        }
+    }
+}
+
+impl Reader<'static, File> {
+    /// Creates a new `Reader` for the CDB at `path`, owning the opened file.
+    ///
+    /// Unlike [`new`](#method.new), there's no `File` binding for the caller
+    /// to keep alive, so the `Reader` can be returned from a function.
+    ///
+    /// ```
+    /// use galvanize::Reader;
+    ///
+    /// let mut cdb_reader = Reader::open("tests/testdata/top250pws.cdb").unwrap();
+    /// assert_eq!(cdb_reader.get_first("letmein".as_bytes()).unwrap(), "10".as_bytes());
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Reader<'static, File>> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        Self::from_parts(Handle::Owned(file), Endianness::Little, Format::Cdb32, file_len)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Reader<'static, Cursor<Mmap>> {
+    /// Creates a new `Reader` for the CDB at `path`, memory mapping the file
+    /// instead of reading it through syscalls.
+    ///
+    /// The header, hash table slots and records are all read straight out of
+    /// the mapped region, so lookups against a file already in the page cache
+    /// don't make any syscalls. The file must not be modified while it's
+    /// mapped, in particular truncating it makes lookups crash instead of
+    /// failing with an error. Replace CDBs by renaming a new file over them.
+    pub fn mmap<P: AsRef<Path>>(path: P) -> Result<Reader<'static, Cursor<Mmap>>> {
+        let file = File::open(path)?;
+        // The caller is responsible for not modifying the file, see above.
+        let map = unsafe { Mmap::map(&file)? };
+        let file_len = map.len() as u64;
+        let handle = Handle::Owned(Cursor::new(map));
+        Self::from_parts(handle, Endianness::Little, Format::Cdb32, file_len)
     }
 }
 
 // Needs to be a file to `truncate` at the end.
 impl<'a> Reader<'a, File> {
+    /// Creates a new `Reader` consuming the provided `file`, like
+    /// [`new`](#method.new) does.
+    ///
+    /// The size of the `file` is taken from its metadata instead of seeking
+    /// to its end, which saves a syscall when opening many small CDBs.
+    pub fn from_file(file: &'a mut File) -> Result<Reader<'a, File>> {
+        let file_len = file.metadata()?.len();
+        Self::from_parts(Handle::Borrowed(file), Endianness::Little, Format::Cdb32, file_len)
+    }
+
+    /// Replace the underlying `file` with the one at `path`, for example to
+    /// pick up a CDB that was atomically replaced by renaming a new file over
+    /// the old one.
+    ///
+    /// The new file is opened and its header parsed before replacing
+    /// anything, so on error this `Reader` keeps using the old `file`. From
+    /// then on, the `Reader` owns the new file. Iterators borrow the `Reader`,
+    /// so none can be in-flight while reopening. The hash function and probe
+    /// sequence in use are kept.
+    pub fn reopen<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let (probing, hasher) = (self.probing, self.hasher);
+        *self = Reader::from_parts(Handle::Owned(file), self.endianness, self.format, file_len)?;
+        // The new file is expected to be written the same way as the old one.
+        self.probing = probing;
+        self.hasher = hasher;
+        Ok(())
+    }
+
     /// Transform this `Reader` into a `Writer` using the same underlying
     /// `file`.
     ///
     /// The underlying file will have its hash table `truncate`d. This will be
     /// regenerated on `Writer` drop.
-    pub fn as_writer(self) -> Result<Writer<'a, File>> {
-        match self.file.seek(SeekFrom::Start(self.table_start as u64)) {
+    ///
+    /// The `file` must have been opened with write permissions, otherwise
+    /// `Error::FileNotWritable` is returned and the `file` is left untouched.
+    pub fn as_writer(mut self) -> Result<Writer<'a, File>> {
+        // Probe for write permissions before modifying anything, so that a
+        // read-only `file` doesn't fail halfway through with a confusing error.
+        if let Err(e) = self.file.write(&[]) {
+            return Err(Error::FileNotWritable(e));
+        }
+        match self.file.seek(SeekFrom::Start(self.table_start)) {
             Ok(_) => {
-                let mut index: Vec<Vec<(u32, u32)>> = vec![Vec::new(); 256];
+                let mut index: Vec<Vec<(Hash, Pos)>> = vec![Vec::new(); 256];
 
-                let buf = &mut [0_u8; 8];
                 // Read hash table until end of file to recreate Writer index.
-                while let Ok(s) = self.file.read(buf) {
-                    if s == 0 {
-                        // EOF
-                        break;
+                while let Ok((h, pos)) = self.read_pair() {
+                    let h = Hash(h as u32);
+                    let pos = Pos(pos);
+                    if pos != Pos(0) {
+                        // Skip empty slots, the tables get rebuilt from scratch.
+                        index[h.bucket()].push((h, pos));
                     }
-                    let h = unpack([buf[0], buf[1], buf[2], buf[3]]);
-                    let pos = unpack([buf[4], buf[5], buf[6], buf[7]]);
-                    index[(h & 0xff) as usize].push((h, pos));
                 }
 
                 // Clear the hash table at the end of the file. It'll be
                 // recreated on `Drop` of the `Writer`.
-                match self.file.set_len(self.table_start as u64) {
+                match self.file.set_len(self.table_start) {
                     Ok(_) => (),
                     Err(e) => return Err(Error::IOError(e)),
                 }
-                Writer::new_with_index(self.file, index)
+                // New records go where the footer was, not after a hole.
+                self.file.seek(SeekFrom::Start(self.table_start))?;
+                let mut writer =
+                    Writer::from_handle(self.file, index, self.endianness, self.format)?;
+                writer.set_probing(self.probing);
+                writer.set_hasher(self.hasher);
+                Ok(writer)
             }
             Err(e) => Err(Error::IOError(e)),
         }
@@ -0,0 +1,117 @@
+//! This module allows you to alternate between reading and writing a CDB.
+use reader::Reader;
+use std::fs::File;
+use std::io;
+use types::{Error, Result};
+use writer::Writer;
+
+/// A CDB `File` that can be appended to and queried in turns, converting
+/// between a [`Reader`](../reader/struct.Reader.html) and a
+/// [`Writer`](../writer/struct.Writer.html) as needed.
+///
+/// Switching from reading to writing truncates the hash tables footer, and
+/// switching back writes it out again, like
+/// [`Reader::as_writer`](../reader/struct.Reader.html#method.as_writer) and
+/// [`Writer::as_reader`](../writer/struct.Writer.html#method.as_reader) do.
+/// Each switch rewrites the whole footer, so batch the writes.
+///
+/// #Example
+///
+/// ```
+/// use galvanize::RwCdb;
+/// use std::fs::OpenOptions;
+///
+/// let mut f = OpenOptions::new()
+///     .read(true)
+///     .write(true)
+///     .create(true)
+///     .truncate(true)
+///     .open("rw_cdb_example.cdb")
+///     .unwrap();
+/// let mut cdb = RwCdb::create(&mut f).unwrap();
+/// for batch in 0..3 {
+///     let key = format!("key{}", batch);
+///     cdb.write().unwrap().put(key.as_bytes(), b"value").unwrap();
+///     assert_eq!(cdb.read().unwrap().len(), batch + 1);
+/// }
+/// ```
+pub struct RwCdb<'a> {
+    /// Current view of the `File`, `None` if a conversion between the two
+    /// failed.
+    state: Option<State<'a>>,
+}
+
+/// The view of a [`RwCdb`](struct.RwCdb.html) `File` in use.
+enum State<'a> {
+    Reading(Reader<'a, File>),
+    Writing(Writer<'a, File>),
+}
+
+impl<'a> RwCdb<'a> {
+    /// Creates a new, empty, CDB in `file`, ready to be written to.
+    pub fn create(file: &'a mut File) -> Result<RwCdb<'a>> {
+        Ok(RwCdb {
+            state: Some(State::Writing(Writer::new(file)?)),
+        })
+    }
+
+    /// Opens the existing CDB in `file`, ready to be read from.
+    ///
+    /// The `file` must have been opened with write permissions before calling
+    /// [`write`](#method.write).
+    pub fn open(file: &'a mut File) -> Result<RwCdb<'a>> {
+        Ok(RwCdb {
+            state: Some(State::Reading(Reader::new(file)?)),
+        })
+    }
+
+    /// Get the `Reader` for the CDB, writing out the footer first if it was
+    /// being written to.
+    ///
+    /// If the conversion fails the `RwCdb` can't be used anymore, and
+    /// further calls return an error.
+    pub fn read(&mut self) -> Result<&mut Reader<'a, File>> {
+        let state = match self.state.take() {
+            Some(State::Writing(writer)) => State::Reading(writer.as_reader()?),
+            Some(state) => state,
+            None => return Err(unusable()),
+        };
+        match self.state.get_or_insert(state) {
+            State::Reading(reader) => Ok(reader),
+            State::Writing(_) => unreachable!(),
+        }
+    }
+
+    /// Get the `Writer` for the CDB, truncating the footer first if it was
+    /// being read from.
+    ///
+    /// If the conversion fails the `RwCdb` can't be used anymore, and
+    /// further calls return an error.
+    pub fn write(&mut self) -> Result<&mut Writer<'a, File>> {
+        let state = match self.state.take() {
+            Some(State::Reading(reader)) => State::Writing(reader.as_writer()?),
+            Some(state) => state,
+            None => return Err(unusable()),
+        };
+        match self.state.get_or_insert(state) {
+            State::Writing(writer) => Ok(writer),
+            State::Reading(_) => unreachable!(),
+        }
+    }
+
+    /// Finish with the CDB, returning its `Reader`.
+    pub fn into_reader(mut self) -> Result<Reader<'a, File>> {
+        self.read()?;
+        match self.state.take() {
+            Some(State::Reading(reader)) => Ok(reader),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Error returned once a `RwCdb` lost its `File` to a failed conversion.
+fn unusable() -> Error {
+    Error::IOError(io::Error::other(
+        "a previous conversion between reading and writing failed",
+    ))
+}
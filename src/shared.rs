@@ -0,0 +1,438 @@
+//! This module allows you to look up keys in a CDB through a shared reference,
+//! so a single reader can be used from several threads at once.
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use helpers::{
+    DjbHasher, Endianness, Format, Hash, Hasher, LinearProbe, Pos, ProbeSequence, HEADER_SIZE,
+    RECORD_HEADER_SIZE, SLOT_SIZE,
+};
+use reader::{parse_header, CdbRead, CowItem, Probe};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+use types::{Error, Result};
+
+/// Positioned reads, which don't depend on nor move a shared cursor.
+///
+/// This is what allows [`SharedReader`](struct.SharedReader.html) lookups to
+/// take `&self`: unlike `Read + Seek`, any number of reads can be in flight at
+/// once.
+pub trait ReadAt {
+    /// Fill all of `buf` with the bytes starting at `offset`.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Size in bytes of the underlying storage.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Borrow the `len` bytes starting at `offset`, if the storage is in
+    /// memory. Otherwise they are copied out with `read_exact_at`.
+    fn slice_at(&self, _offset: u64, _len: usize) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Uses `FileExt::read_exact_at` on Unix and `FileExt::seek_read` on Windows.
+///
+/// On Windows `seek_read` also moves the file cursor, so don't mix positioned
+/// reads with `Read` or `Seek` calls on the same `File`.
+#[cfg(any(unix, windows))]
+impl ReadAt for File {
+    #[cfg(unix)]
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        ::std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    let tmp = buf;
+                    buf = &mut tmp[n..];
+                    offset += n as u64;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let start = offset as usize;
+        match start.checked_add(buf.len()).and_then(|end| self.get(start..end)) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            )),
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let start = offset as usize;
+        self.get(start..start.checked_add(len)?)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self[..].read_exact_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self[..].size()
+    }
+
+    fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        self[..].slice_at(offset, len)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ReadAt for Mmap {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self[..].read_exact_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self[..].size()
+    }
+
+    fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        self[..].slice_at(offset, len)
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for &R {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        (**self).read_exact_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        (**self).slice_at(offset, len)
+    }
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for Arc<R> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        (**self).read_exact_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        (**self).slice_at(offset, len)
+    }
+}
+
+/// Read only access to a CDB whose lookups take `&self`.
+///
+/// All reads are positioned reads through [`ReadAt`](trait.ReadAt.html), so
+/// a `SharedReader<File>` is `Sync` and can be shared between threads behind
+/// an `Arc`, without a lock.
+///
+/// The CDB must not be modified while a `SharedReader` is reading it, as
+/// lookups would then return garbage or fail with an error.
+///
+/// #Example
+///
+/// ```
+/// use galvanize::SharedReader;
+/// use std::fs::File;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let f = File::open("tests/testdata/top250pws.cdb").unwrap();
+/// let cdb_reader = Arc::new(SharedReader::new(f).unwrap());
+///
+/// let shared = Arc::clone(&cdb_reader);
+/// let lookup = thread::spawn(move || shared.get_first("letmein".as_bytes()).unwrap());
+/// assert_eq!(lookup.join().unwrap(), "10".as_bytes());
+/// assert_eq!(cdb_reader.get("letmein".as_bytes()), vec!["10".as_bytes()]);
+/// ```
+#[derive(Debug)]
+pub struct SharedReader<R: ReadAt> {
+    /// Storage to read values from.
+    inner: R,
+    /// Index for the contents of the CDB.
+    index: Vec<(u64, u32)>,
+    /// Position in the storage where the hash table starts.
+    table_start: u64,
+    /// How many elements are there in the CDB.
+    length: usize,
+    /// Byte order of the 32-bit quantities in the storage.
+    endianness: Endianness,
+    /// Order in which hash table slots are probed.
+    probing: &'static dyn ProbeSequence,
+    /// Hash function the keys were placed with.
+    hasher: &'static dyn Hasher,
+}
+
+impl<R: ReadAt> SharedReader<R> {
+    /// Creates a new `SharedReader` reading the CDB in `inner`.
+    pub fn new(inner: R) -> Result<SharedReader<R>> {
+        Self::with_endianness(inner, Endianness::Little)
+    }
+
+    /// Creates a new `SharedReader` reading the CDB in `inner`, whose 32-bit
+    /// quantities are stored using `endianness`.
+    pub fn with_endianness(inner: R, endianness: Endianness) -> Result<SharedReader<R>> {
+        let size = inner.size()?;
+        if size < HEADER_SIZE as u64 {
+            return Err(Error::CDBTooSmall { size });
+        }
+        let mut buf = [0; HEADER_SIZE];
+        inner.read_exact_at(&mut buf, 0)?;
+        let header = parse_header(&buf, endianness, Format::Cdb32)?;
+        Ok(SharedReader {
+            inner,
+            index: header.index,
+            table_start: header.table_start,
+            length: header.length,
+            endianness,
+            probing: &LinearProbe,
+            hasher: &DjbHasher,
+        })
+    }
+
+    /// Creates a new `SharedReader` reading the CDB in `inner`, whose keys
+    /// were hashed with `hasher`, see
+    /// [`Reader::with_hasher`](../reader/struct.Reader.html#method.with_hasher).
+    pub fn with_hasher(inner: R, hasher: &'static dyn Hasher) -> Result<SharedReader<R>> {
+        let mut reader = Self::new(inner)?;
+        reader.hasher = hasher;
+        Ok(reader)
+    }
+
+    /// How many `(key, value)` pairs are there in this Read Only CDB.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether there are no `(key, value)` pairs in this Read Only CDB.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Use `probing` to look up keys instead of the standard linear probing,
+    /// see [`Reader::set_probing`](../reader/struct.Reader.html#method.set_probing).
+    pub fn set_probing(&mut self, probing: &'static dyn ProbeSequence) {
+        self.probing = probing;
+    }
+
+    /// Return a `Vec` of all the values under the given `key`.
+    pub fn get(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), Format::Cdb32, self.probing);
+        let mut values = vec![];
+        while let Ok(Some((pos, dlen))) = self.next_match(key, &mut probe) {
+            match self.read_value(pos, dlen) {
+                Ok(value) => values.push(value),
+                Err(_) => break,
+            }
+        }
+        values
+    }
+
+    /// Pull the first value stored under `key`.
+    pub fn get_first(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.get_from_pos(key, 0)
+    }
+
+    /// Whether there's any value stored under `key`.
+    ///
+    /// Only the keys of the records probed are read, never their values.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), Format::Cdb32, self.probing);
+        Ok(self.next_match(key, &mut probe)?.is_some())
+    }
+
+    /// Pull the value at position `index` of the values stored under `key`.
+    ///
+    /// Errors are the same as for
+    /// [`Reader::get_from_pos`](../reader/struct.Reader.html#method.get_from_pos).
+    pub fn get_from_pos(&self, key: &[u8], index: u32) -> Result<Vec<u8>> {
+        let mut probe = Probe::new(&self.index, self.hasher.hash(key), Format::Cdb32, self.probing);
+        if probe.nslots == 0 {
+            return Err(Error::KeyNotInCDB);
+        }
+
+        let mut counter = 0;
+        while let Some((pos, dlen)) = self.next_match(key, &mut probe)? {
+            if counter == index {
+                return self.read_value(pos, dlen);
+            }
+            counter += 1;
+        }
+        if counter == 0 {
+            Err(Error::KeyNotInCDB)
+        } else {
+            Err(Error::OccurrenceOutOfRange { available: counter })
+        }
+    }
+
+    /// Iterate over all the `(key, value)` pairs, in the order they were
+    /// written, stopping at the first corrupt record.
+    ///
+    /// The pairs are borrowed from the storage when it's in memory.
+    pub fn iter(&self) -> SharedIter<'_, R> {
+        SharedIter {
+            reader: self,
+            pos: HEADER_SIZE as u64,
+        }
+    }
+
+    /// Get back the storage this `SharedReader` was reading from.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Find the next record in the `probe`d bucket whose key is `key`,
+    /// returning the position and length of its value.
+    fn next_match(&self, key: &[u8], probe: &mut Probe) -> Result<Option<(u64, u32)>> {
+        let mut buf = [0; SLOT_SIZE];
+        while let Some(pos) = probe.next_slot() {
+            self.inner.read_exact_at(&mut buf, pos)?;
+            let rec_h = Hash(self.endianness.unpack([buf[0], buf[1], buf[2], buf[3]]));
+            let Pos(rec_pos) =
+                Pos(u64::from(self.endianness.unpack([buf[4], buf[5], buf[6], buf[7]])));
+
+            if rec_pos == 0 {
+                // Empty slot, key not in file.
+                return Ok(None);
+            } else if rec_h == probe.h {
+                let key_pos = rec_pos + RECORD_HEADER_SIZE as u64;
+                if rec_pos < HEADER_SIZE as u64 || key_pos > self.table_start {
+                    return Err(Error::CorruptRecord { position: rec_pos });
+                }
+                let mut header = [0; RECORD_HEADER_SIZE];
+                self.inner.read_exact_at(&mut header, rec_pos)?;
+                let klen = self.endianness.unpack([header[0], header[1], header[2], header[3]]);
+                let dlen = self.endianness.unpack([header[4], header[5], header[6], header[7]]);
+                if key_pos + u64::from(klen) + u64::from(dlen) > self.table_start {
+                    return Err(Error::CorruptRecord { position: rec_pos });
+                }
+                if klen as usize != key.len() {
+                    continue;
+                }
+                let mut rec_key = vec![0; klen as usize];
+                self.inner.read_exact_at(&mut rec_key, key_pos)?;
+                if rec_key == key {
+                    return Ok(Some((key_pos + u64::from(klen), dlen)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The `len` bytes stored at `pos`, borrowed from the storage if possible.
+    fn bytes_at(&self, pos: u64, len: u32) -> Result<Cow<'_, [u8]>> {
+        if let Some(bytes) = self.inner.slice_at(pos, len as usize) {
+            return Ok(Cow::Borrowed(bytes));
+        }
+        let mut buf = vec![0; len as usize];
+        self.inner.read_exact_at(&mut buf, pos)?;
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Read the `dlen` bytes long value stored at `pos`.
+    fn read_value(&self, pos: u64, dlen: u32) -> Result<Vec<u8>> {
+        let mut value = vec![0; dlen as usize];
+        self.inner.read_exact_at(&mut value, pos)?;
+        Ok(value)
+    }
+}
+
+impl SharedReader<Arc<[u8]>> {
+    /// Creates a new `SharedReader` over an in-memory CDB.
+    ///
+    /// Lookups read straight out of the `bytes`, which are kept behind an
+    /// `Arc` so the storage can be handed out to other readers cheaply.
+    pub fn from_bytes<B: Into<Arc<[u8]>>>(bytes: B) -> Result<SharedReader<Arc<[u8]>>> {
+        Self::new(bytes.into())
+    }
+}
+
+impl<R: ReadAt> CdbRead for SharedReader<R> {
+    fn iter<'r>(&'r mut self) -> Box<dyn Iterator<Item = CowItem<'r>> + 'r> {
+        Box::new(SharedReader::iter(self))
+    }
+}
+
+/// Iterator over the `(key, value)` pairs in a CDB, returned by
+/// [`SharedReader::iter`](struct.SharedReader.html#method.iter).
+#[derive(Debug)]
+pub struct SharedIter<'r, R: ReadAt + 'r> {
+    reader: &'r SharedReader<R>,
+    /// Position of the next record.
+    pos: u64,
+}
+
+impl<'r, R: ReadAt + 'r> SharedIter<'r, R> {
+    fn next_item(&mut self) -> Result<Option<CowItem<'r>>> {
+        let reader = self.reader;
+        if self.pos >= reader.table_start {
+            return Ok(None);
+        }
+        let corrupt = Error::CorruptRecord { position: self.pos };
+        let key_pos = self.pos + RECORD_HEADER_SIZE as u64;
+        if key_pos > reader.table_start {
+            return Err(corrupt);
+        }
+        let mut buf = [0; RECORD_HEADER_SIZE];
+        reader.inner.read_exact_at(&mut buf, self.pos)?;
+        let klen = reader.endianness.unpack([buf[0], buf[1], buf[2], buf[3]]);
+        let dlen = reader.endianness.unpack([buf[4], buf[5], buf[6], buf[7]]);
+        let end = key_pos + u64::from(klen) + u64::from(dlen);
+        if end > reader.table_start {
+            return Err(corrupt);
+        }
+        let key = reader.bytes_at(key_pos, klen)?;
+        let value = reader.bytes_at(key_pos + u64::from(klen), dlen)?;
+        self.pos = end;
+        Ok(Some((key, value)))
+    }
+}
+
+impl<'r, R: ReadAt + 'r> Iterator for SharedIter<'r, R> {
+    type Item = CowItem<'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_item() {
+            Ok(item) => item,
+            Err(_) => {
+                // Don't keep yielding errors at the same corrupt record.
+                self.pos = self.reader.table_start;
+                None
+            }
+        }
+    }
+}
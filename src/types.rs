@@ -3,6 +3,11 @@
 //!
 //! [`Result<T>`](type.Result.html) can be either `T` or an
 //! [`Error`](enum.Error.html).
+#[cfg(feature = "serde")]
+use serde::de::{Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::error;
 use std::fmt;
@@ -13,9 +18,60 @@ use std::result;
 #[derive(Debug)]
 pub enum Error {
     /// The CDB is under 2048 bytes. The file being read is not a valid CDB.
-    CDBTooSmall,
+    ///
+    /// `size` is the observed length of the file in bytes.
+    CDBTooSmall { size: u64 },
     /// The `key` being fetched isn't in the CDB.
     KeyNotInCDB,
+    /// The `key` being written hashes to `0`, which older readers of this
+    /// crate interpret as an empty hash table slot, so they would never find
+    /// its values. Only returned by a `Writer` in strict mode.
+    UnrepresentableKey,
+    /// The lookup probed more hash table slots than it was allowed to,
+    /// without finding the `key` or an empty slot. This doesn't mean that
+    /// the `key` isn't in the CDB.
+    ProbeBudgetExceeded,
+    /// The file can't be written to, but it needs to be in order to append
+    /// to the CDB. Open it with write permissions, for example with
+    /// `OpenOptions::new().read(true).write(true)`. It wraps the original
+    /// `std::io::Error`.
+    FileNotWritable(IOError),
+    /// A streamed value didn't have the declared length of `expected` bytes.
+    LengthMismatch { expected: u32 },
+    /// The value is `size` bytes long, which doesn't fit in a buffer of
+    /// `capacity` bytes.
+    EntryTooLarge { size: u32, capacity: usize },
+    /// The header points the non-empty hash table for `bucket` inside the
+    /// header itself, or somewhere that overlaps with the records or the
+    /// other hash tables. The file being read is not a valid CDB.
+    CorruptTable { bucket: usize },
+    /// The `key` is in the CDB, but there are only `available` values under
+    /// it, so there's no value at the requested position.
+    OccurrenceOutOfRange { available: u32 },
+    /// The `offset` to start iterating from, or of a `ValueHandle`, is
+    /// outside of the records section of the CDB.
+    InvalidOffset { offset: u64 },
+    /// The record at `position` extends past the records section of the
+    /// CDB. The file is damaged.
+    CorruptRecord { position: u64 },
+    /// A key or value of `size` bytes, or a record ending at position `size`
+    /// of the file, doesn't fit in the lengths and positions of the CDB's
+    /// `Format`. Nothing was written.
+    RecordTooLarge { size: u64 },
+    /// The hash tables footer would start a table at position `size`, which
+    /// doesn't fit in the positions of the CDB's `Format`. The records were
+    /// written, but not the footer, so the CDB isn't complete.
+    CDBTooBig { size: u64 },
+    /// The dump being loaded doesn't follow the `cdbmake` format: at byte
+    /// `offset` of it there should have been `expected`.
+    MalformedDump {
+        offset: u64,
+        expected: &'static str,
+    },
+    /// The hex encoded key passed to
+    /// [`decode_key`](../helpers/fn.decode_key.html) has an invalid or
+    /// missing digit at byte `offset`.
+    MalformedKey { offset: usize },
     /// There was an error accessing the file.  It wraps the original
     /// `std::io::Error`.
     IOError(IOError),
@@ -23,11 +79,283 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// How the size of a CDB is split between its sections, as returned by
+/// [`Reader::size_breakdown`](../reader/struct.Reader.html#method.size_breakdown).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeBreakdown {
+    /// Bytes used by the header, always 2048.
+    pub header: u64,
+    /// Bytes used by the records, keys and values included.
+    pub records: u64,
+    /// Bytes used by the hash tables footer.
+    pub footer: u64,
+}
+
+impl SizeBreakdown {
+    /// Size of the whole CDB in bytes.
+    pub fn total(&self) -> u64 {
+        self.header + self.records + self.footer
+    }
+}
+
+/// Layout of a single hash table, as returned by
+/// [`Reader::table_info`](../reader/struct.Reader.html#method.table_info).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableInfo {
+    /// Position in the file where the hash table starts.
+    pub position: u64,
+    /// How many slots there are in the hash table.
+    pub slots: u32,
+    /// How many of the slots point to a record.
+    pub occupied: u32,
+}
+
+/// Where a value is stored in a CDB, as returned by
+/// [`Reader::locate`](../reader/struct.Reader.html#method.locate).
+///
+/// A `ValueHandle` is only meaningful for the CDB it was returned for, and
+/// can be passed to
+/// [`Reader::read_handle`](../reader/struct.Reader.html#method.read_handle) to
+/// read the value later on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ValueHandle {
+    /// Position in the file where the value starts.
+    pub offset: u64,
+    /// Length in bytes of the value.
+    pub len: u32,
+}
+
+/// Aggregate statistics about the hash table probing of a set of lookups, as
+/// returned by
+/// [`Reader::profile_lookups`](../reader/struct.Reader.html#method.profile_lookups).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LookupProfile {
+    /// How many lookups were profiled.
+    pub lookups: usize,
+    /// Fewest hash table slots probed by a single lookup.
+    pub min_probes: u32,
+    /// Most hash table slots probed by a single lookup.
+    pub max_probes: u32,
+    /// Mean hash table slots probed per lookup.
+    pub mean_probes: f64,
+    /// 99th percentile of hash table slots probed per lookup.
+    pub p99_probes: u32,
+    /// Fraction of the lookups that needed more than two disk accesses,
+    /// counting both slots probed and records read.
+    pub over_two_accesses: f64,
+}
+
+/// All the records of a CDB with the values grouped by key, as returned by
+/// [`Reader::to_map`](../reader/struct.Reader.html#method.to_map).
+///
+/// With the `serde` feature enabled this can be serialized and deserialized
+/// with any serde format. Keys can be arbitrary bytes, which formats like
+/// JSON don't allow as map keys, so it is serialized as a sequence of
+/// `(key, values)` pairs in key order instead of as a map. Keys and values
+/// are serialized as byte sequences, which JSON writes as arrays of numbers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CdbMap(pub BTreeMap<Vec<u8>, Vec<Vec<u8>>>);
+
+#[cfg(feature = "serde")]
+impl Serialize for CdbMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for entry in &self.0 {
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for CdbMap {
+    /// The values of a key that appears in more than one pair are appended
+    /// in order.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        let pairs: Vec<(Vec<u8>, Vec<Vec<u8>>)> = Vec::deserialize(deserializer)?;
+        let mut map: BTreeMap<Vec<u8>, Vec<Vec<u8>>> = BTreeMap::new();
+        for (key, values) in pairs {
+            map.entry(key).or_default().extend(values);
+        }
+        Ok(CdbMap(map))
+    }
+}
+
+/// How evenly the records of a CDB are spread over its hash tables, as
+/// returned by [`Reader::stats`](../reader/struct.Reader.html#method.stats).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// How many records the hash tables point to.
+    pub records: usize,
+    /// Statistics for each of the 256 hash tables, in bucket order.
+    pub tables: Vec<TableStats>,
+    /// Most slots past its first one that any key has to probe, over all the
+    /// hash tables.
+    pub max_probe_distance: u32,
+}
+
+/// Statistics for a single hash table, as part of [`Stats`](struct.Stats.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TableStats {
+    /// How many slots there are in the hash table.
+    pub slots: u32,
+    /// How many of the slots point to a record.
+    pub occupied: u32,
+    /// Fraction of the slots that point to a record, `0.0` for an empty
+    /// table.
+    pub fill_ratio: f64,
+    /// Most slots past its first one that any key in this table has to probe,
+    /// `0` when every record landed in its first slot.
+    pub max_probe_distance: u32,
+}
+
+/// Outcome of checking the structural integrity of a CDB, as returned by
+/// [`Reader::verify`](../reader/struct.Reader.html#method.verify).
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// How many records were found in the records section.
+    pub records: usize,
+    /// Size of the CDB in bytes.
+    pub total_bytes: u64,
+    /// Every problem found in the CDB. Empty if the CDB is well formed.
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl VerifyReport {
+    /// Whether no problems were found in the CDB.
+    pub fn is_ok(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+/// A structural problem found in a CDB.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The hash table for `bucket` starts inside the header or extends past
+    /// the end of the file.
+    TableOutOfBounds {
+        bucket: usize,
+        position: u64,
+        slots: u32,
+    },
+    /// The record at `position` has a key and value lengths that extend past
+    /// the start of the hash tables.
+    RecordOutOfBounds { position: u64 },
+    /// The number of `records` found differs from the number of records the
+    /// header claims there are.
+    RecordCountMismatch { records: usize, expected: usize },
+    /// No slot of the hash table the key of the record at `position` belongs
+    /// to points at it with the key's hash, so lookups can't find it.
+    UnreachableRecord { position: u64 },
+    /// A slot of the hash table for `bucket` points at `position`, which
+    /// isn't the start of a record.
+    DanglingSlot { bucket: usize, position: u64 },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Anomaly::TableOutOfBounds {
+                bucket,
+                position,
+                slots,
+            } => write!(
+                f,
+                "offset {}: hash table {} with {} slots is out of bounds",
+                position, bucket, slots
+            ),
+            Anomaly::RecordOutOfBounds { position } => write!(
+                f,
+                "offset {}: record extends into the hash tables",
+                position
+            ),
+            Anomaly::RecordCountMismatch { records, expected } => write!(
+                f,
+                "found {} records, but the header accounts for {}",
+                records, expected
+            ),
+            Anomaly::UnreachableRecord { position } => write!(
+                f,
+                "offset {}: record can't be found through the hash tables",
+                position
+            ),
+            Anomaly::DanglingSlot { bucket, position } => write!(
+                f,
+                "offset {}: hash table {} points at something other than a record",
+                position, bucket
+            ),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::CDBTooSmall => write!(f, "File too small to be a CDB"),
+            Error::CDBTooSmall { size } => write!(
+                f,
+                "File too small to be a CDB: file is {} bytes, smaller than its header",
+                size
+            ),
             Error::KeyNotInCDB => write!(f, "The key is not in the CDB"),
+            Error::UnrepresentableKey => {
+                write!(f, "The key hashes to 0 and can't be read back by older readers")
+            }
+            Error::ProbeBudgetExceeded => write!(f, "The lookup exceeded its probe budget"),
+            Error::FileNotWritable(ref e) => write!(
+                f,
+                "The file must be opened with write permissions: {}",
+                e
+            ),
+            Error::LengthMismatch { expected } => write!(
+                f,
+                "The value doesn't have the declared length of {} bytes",
+                expected
+            ),
+            Error::EntryTooLarge { size, capacity } => write!(
+                f,
+                "The value is {} bytes long, but only {} bytes fit in the buffer",
+                size, capacity
+            ),
+            Error::CorruptTable { bucket } => write!(
+                f,
+                "The hash table {} is misplaced, the file is not a valid CDB",
+                bucket
+            ),
+            Error::OccurrenceOutOfRange { available } => write!(
+                f,
+                "The key only has {} values in the CDB",
+                available
+            ),
+            Error::InvalidOffset { offset } => write!(
+                f,
+                "The offset {} is outside of the records section of the CDB",
+                offset
+            ),
+            Error::CorruptRecord { position } => write!(
+                f,
+                "The record at offset {} is corrupt, the CDB is damaged",
+                position
+            ),
+            Error::RecordTooLarge { size } => write!(
+                f,
+                "The record doesn't fit in the CDB, {} is over the limit",
+                size
+            ),
+            Error::CDBTooBig { size } => write!(
+                f,
+                "The hash tables don't fit in the CDB, a table would start at {}",
+                size
+            ),
+            Error::MalformedDump { offset, expected } => write!(
+                f,
+                "The dump is malformed, expected {} at byte {}",
+                expected, offset
+            ),
+            Error::MalformedKey { offset } => write!(
+                f,
+                "The key isn't hex encoded, expected a hex digit at byte {}",
+                offset
+            ),
             Error::IOError(ref e) => write!(f, "IO Error: {}", e),
         }
     }
@@ -37,8 +365,21 @@ impl error::Error for Error {
     #[allow(deprecated)]
     fn description(&self) -> &str {
         match *self {
-            Error::CDBTooSmall => "The file is too small to be a valid CDB",
+            Error::CDBTooSmall { .. } => "The file is too small to be a valid CDB",
             Error::KeyNotInCDB => "The key is not in the CDB",
+            Error::UnrepresentableKey => "The key can't be read back by older readers",
+            Error::ProbeBudgetExceeded => "The lookup exceeded its probe budget",
+            Error::FileNotWritable(_) => "The file must be opened with write permissions",
+            Error::LengthMismatch { .. } => "The value doesn't have the declared length",
+            Error::EntryTooLarge { .. } => "The value doesn't fit in the buffer",
+            Error::CorruptTable { .. } => "A hash table is misplaced",
+            Error::OccurrenceOutOfRange { .. } => "The key doesn't have that many values",
+            Error::InvalidOffset { .. } => "The offset is outside of the records section",
+            Error::CorruptRecord { .. } => "A record in the CDB is corrupt",
+            Error::RecordTooLarge { .. } => "The record doesn't fit in the CDB",
+            Error::CDBTooBig { .. } => "The hash tables don't fit in the CDB",
+            Error::MalformedDump { .. } => "The dump doesn't follow the cdbmake format",
+            Error::MalformedKey { .. } => "The key isn't hex encoded",
             // The underlying error already impl `Error`, so we defer to its
             // implementation.
             Error::IOError(ref e) => e.description(),
@@ -47,8 +388,21 @@ impl error::Error for Error {
 
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
-            Error::CDBTooSmall => None,
+            Error::CDBTooSmall { .. } => None,
             Error::KeyNotInCDB => None,
+            Error::UnrepresentableKey => None,
+            Error::ProbeBudgetExceeded => None,
+            Error::FileNotWritable(ref e) => Some(e),
+            Error::LengthMismatch { .. } => None,
+            Error::EntryTooLarge { .. } => None,
+            Error::CorruptTable { .. } => None,
+            Error::OccurrenceOutOfRange { .. } => None,
+            Error::InvalidOffset { .. } => None,
+            Error::CorruptRecord { .. } => None,
+            Error::RecordTooLarge { .. } => None,
+            Error::CDBTooBig { .. } => None,
+            Error::MalformedDump { .. } => None,
+            Error::MalformedKey { .. } => None,
             Error::IOError(ref e) => Some(e),
         }
     }
@@ -1,8 +1,21 @@
 //! This module allows you to write to a CDB.
-use helpers::{hash, pack};
+use helpers::{
+    hash, pack, unpack, DjbHasher, Endianness, Format, Handle, Hash, Hasher, LinearProbe, Offset,
+    Pos, ProbeSequence, HEADER_SIZE, RECORD_HEADER_SIZE, SLOT_SIZE,
+};
 use reader::Reader;
-use std::io::{Read, Seek, SeekFrom, Write};
-use types::Result;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use types::{Error, Result};
+
+/// Number of `AtomicWriter`s created by this process, to give each one its
+/// own temporary file.
+static ATOMIC_WRITERS: AtomicUsize = AtomicUsize::new(0);
 
 /// Allows you to create a (or append to) CDB.
 ///
@@ -38,9 +51,67 @@ use types::Result;
 /// ```
 pub struct Writer<'a, F: Write + Read + Seek + 'a> {
     /// Opened file to write values into.
-    file: Option<&'a mut F>,
+    file: Option<Handle<'a, F>>,
     /// Working hash table for the contents of the CDB.
-    index: Vec<Vec<(u32, u32)>>,
+    index: Vec<Vec<(Hash, Pos)>>,
+    /// Whether to reject keys that can't be read back.
+    strict: bool,
+    /// Byte order of the 32-bit quantities written to the `file`.
+    endianness: Endianness,
+    /// Position in the `file` where the last record written ends.
+    records_end: u64,
+    /// Where the `index` is persisted as it's built, if anywhere.
+    journal: Option<Journal>,
+    /// Order in which hash table slots are probed when placing records.
+    probing: &'static dyn ProbeSequence,
+    /// Width of the positions and lengths written to the `file`.
+    format: Format,
+    /// Hash function for the keys.
+    hasher: &'static dyn Hasher,
+}
+
+/// Sidecar file holding a `(hash, position)` entry for every record written,
+/// so that an interrupted build can be resumed.
+#[derive(Debug)]
+struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+/// A key in a [`Writer`](struct.Writer.html), which may or may not have been
+/// written yet, returned by [`Writer::entry`](struct.Writer.html#method.entry).
+///
+/// Records can't be changed once written, so unlike `HashMap`'s entries there
+/// is no way to modify or remove the values of an occupied entry: inserting
+/// only ever happens when the key is absent.
+pub struct Entry<'w, 'a: 'w, F: Write + Read + Seek + 'a> {
+    writer: &'w mut Writer<'a, F>,
+    key: &'w [u8],
+    /// Whether the `key` was already written.
+    occupied: bool,
+}
+
+impl<'w, 'a: 'w, F: Write + Read + Seek + 'a> Entry<'w, 'a, F> {
+    /// Whether a value was already written for this key.
+    pub fn is_occupied(&self) -> bool {
+        self.occupied
+    }
+
+    /// Write `value` for this key, if the key was absent. Returns whether the
+    /// `value` was written.
+    pub fn or_insert(self, value: &[u8]) -> Result<bool> {
+        self.or_insert_with(|| value.to_vec())
+    }
+
+    /// Write the value returned by `default` for this key, if the key was
+    /// absent. `default` is only called when the key was absent.
+    pub fn or_insert_with<D: FnOnce() -> Vec<u8>>(self, default: D) -> Result<bool> {
+        if self.occupied {
+            return Ok(false);
+        }
+        self.writer.put(self.key, &default())?;
+        Ok(true)
+    }
 }
 
 impl<'a, F: Write + Read + Seek + 'a> Writer<'a, F> {
@@ -48,73 +119,584 @@ impl<'a, F: Write + Read + Seek + 'a> Writer<'a, F> {
     ///
     /// The `file` must allow writes to be performed.
     pub fn new(file: &'a mut F) -> Result<Writer<'a, F>> {
+        Self::with_endianness(file, Endianness::Little)
+    }
+
+    /// Creates a new `Writer` consuming the provided `file`, reserving room
+    /// in its index for `expected_records` records up front.
+    ///
+    /// This is only a hint to avoid growing the index while writing millions
+    /// of records, the CDB written is the same as with `new`.
+    pub fn with_capacity(file: &'a mut F, expected_records: usize) -> Result<Writer<'a, F>> {
+        let mut writer = Self::new(file)?;
+        for table in &mut writer.index {
+            table.reserve(expected_records / 256);
+        }
+        Ok(writer)
+    }
+
+    /// Creates a new `Writer` consuming the provided `file`, storing 32-bit
+    /// quantities using `endianness` instead of the little-endian byte order
+    /// mandated by the CDB specification.
+    ///
+    /// This is only meant for interoperating with legacy tools, the result
+    /// must be read with `Reader::with_endianness`.
+    pub fn with_endianness(file: &'a mut F, endianness: Endianness) -> Result<Writer<'a, F>> {
         file.seek(SeekFrom::Start(0))?;
-        file.write_all(&[0; 2048])?;
+        file.write_all(&[0; HEADER_SIZE])?;
 
-        Self::new_with_index(file, vec![Vec::new(); 256])
+        Self::from_handle(Handle::Borrowed(file), vec![Vec::new(); 256], endianness, Format::Cdb32)
+    }
+
+    /// Creates a new `Writer` consuming the provided `file`, storing positions
+    /// and lengths as per `format`.
+    ///
+    /// `Format::Cdb64` lifts the 4GB limit of standard CDBs. The result must
+    /// be read with `Reader::new_with_format` using the same `format`.
+    pub fn new_with_format(file: &'a mut F, format: Format) -> Result<Writer<'a, F>> {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&vec![0; format.header_size()])?;
+
+        let index = vec![Vec::new(); 256];
+        Self::from_handle(Handle::Borrowed(file), index, Endianness::Little, format)
+    }
+
+    /// Creates a new `Writer` consuming the provided `file`, and write all the
+    /// values in `map` into it, in key order.
+    ///
+    /// This is the counterpart of `Reader::to_map`.
+    pub fn from_map(
+        file: &'a mut F,
+        map: &BTreeMap<Vec<u8>, Vec<Vec<u8>>>,
+    ) -> Result<Writer<'a, F>> {
+        let mut writer = Self::new(file)?;
+        for (key, values) in map {
+            for value in values {
+                writer.put(key, value)?;
+            }
+        }
+        Ok(writer)
+    }
+
+    /// Creates a new `Writer` consuming the provided `file`, hashing keys
+    /// with `hasher` instead of the DJB hash.
+    ///
+    /// The result must be read with `Reader::with_hasher` using the same
+    /// `hasher`, other CDB tools won't find its keys.
+    pub fn with_hasher(file: &'a mut F, hasher: &'static dyn Hasher) -> Result<Writer<'a, F>> {
+        let mut writer = Self::new(file)?;
+        writer.hasher = hasher;
+        Ok(writer)
+    }
+
+    /// Creates a new `Writer` for a CDB embedded in `file` starting at
+    /// position `base`, leaving everything before `base` untouched.
+    ///
+    /// The positions stored in the CDB are relative to its own start, and it
+    /// must be read with `Reader::new_at` using the same `base`, and the
+    /// length of the finalized CDB.
+    pub fn new_at(file: &'a mut F, base: u64) -> Result<Writer<'a, Offset<&'a mut F>>> {
+        let mut file = Offset::new(file, base);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&[0; HEADER_SIZE])?;
+
+        let index = vec![Vec::new(); 256];
+        Writer::from_handle(Handle::Owned(file), index, Endianness::Little, Format::Cdb32)
     }
 
     /// Used by `Reader::as_writer` method, to prepopulate the index from the
     /// underlying `file`.
     pub fn new_with_index(file: &'a mut F, index: Vec<Vec<(u32, u32)>>) -> Result<Writer<'a, F>> {
+        let index = index
+            .into_iter()
+            .map(|tbl| {
+                tbl.into_iter()
+                    .map(|(h, pos)| (Hash(h), Pos(u64::from(pos))))
+                    .collect()
+            })
+            .collect();
+        Self::from_handle(Handle::Borrowed(file), index, Endianness::Little, Format::Cdb32)
+    }
+
+    /// Creates a new `Writer` from either a borrowed or an owned `file`.
+    pub(crate) fn from_handle(
+        mut file: Handle<'a, F>,
+        index: Vec<Vec<(Hash, Pos)>>,
+        endianness: Endianness,
+        format: Format,
+    ) -> Result<Writer<'a, F>> {
+        let records_end = file.stream_position()?;
         Ok(Writer {
             file: Some(file),
             index,
+            strict: false,
+            endianness,
+            records_end,
+            journal: None,
+            probing: &LinearProbe,
+            format,
+            hasher: &DjbHasher,
         })
     }
 
+    /// Creates a new `Writer` consuming the provided `file`, that also
+    /// appends an entry to the journal file at `journal_path` for every
+    /// record written.
+    ///
+    /// If the build is interrupted, it can be continued with
+    /// [`Writer::resume`](#method.resume) instead of starting over. The
+    /// journal is deleted once the hash tables footer has been written.
+    pub fn with_journal<P: AsRef<Path>>(file: &'a mut F, journal_path: P) -> Result<Writer<'a, F>> {
+        let mut writer = Self::new(file)?;
+        let path = journal_path.as_ref().to_path_buf();
+        writer.journal = Some(Journal {
+            file: File::create(&path)?,
+            path,
+        });
+        Ok(writer)
+    }
+
+    /// How many records have been written into this CDB.
+    pub fn len(&self) -> usize {
+        self.index.iter().map(Vec::len).sum()
+    }
+
+    /// Whether no records have been written into this CDB.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Enable or disable strict mode, disabled by default.
+    ///
+    /// Like cdb, readers treat a hash table slot holding a position of `0` as
+    /// empty, so keys whose DJB hash is exactly `0` are found. Older readers
+    /// of this crate keyed empty slots off the hash instead, and never find
+    /// such keys. In strict mode, `put` returns `Error::UnrepresentableKey`
+    /// for them, for CDBs that must stay readable by those readers.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Place records in the hash tables using `probing` instead of the
+    /// standard linear probing, for CDB variants that need it. The result
+    /// must be read with the same [`ProbeSequence`](../helpers/trait.ProbeSequence.html).
+    pub fn set_probing(&mut self, probing: &'static dyn ProbeSequence) {
+        self.probing = probing;
+    }
+
+    /// Hash keys with `hasher`, carried over from a `Reader`.
+    pub(crate) fn set_hasher(&mut self, hasher: &'static dyn Hasher) {
+        self.hasher = hasher;
+    }
+
     /// Write `value` for `key` into this CDB.
+    ///
+    /// If the `key` or the `value` is too long, or the CDB would grow past
+    /// what its positions can address, `Error::RecordTooLarge` is returned.
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_prehashed(key, self.hasher.hash(key), value)
+    }
+
+    /// Write `value` for `key` into this CDB, using the already computed hash
+    /// `h` of the `key` instead of hashing it again.
+    ///
+    /// The `key` bytes are written verbatim, `h` is only used to place the
+    /// record in the hash tables. If `h` isn't the hash of `key`, the record
+    /// won't be found by readers. This is checked in debug builds.
+    pub fn put_prehashed(&mut self, key: &[u8], h: u32, value: &[u8]) -> Result<()> {
+        debug_assert_eq!(h, self.hasher.hash(key), "wrong hash for key {:?}", key);
+        if self.strict && h == 0 {
+            return Err(Error::UnrepresentableKey);
+        }
+
+        let pos = self.file.as_mut().unwrap().stream_position()?;
+        let header = self.record_header(pos, key.len() as u64, value.len() as u64)?;
         let file = self.file.as_mut().unwrap();
-        let pos = file.seek(SeekFrom::Current(0))? as u32;
-        file.write_all(&pack(key.len() as u32))?;
-        file.write_all(&pack(value.len() as u32))?;
+        file.write_all(&header)?;
 
         file.write_all(key)?;
         file.write_all(value)?;
+        self.records_end = pos + header.len() as u64 + key.len() as u64 + value.len() as u64;
+
+        self.add_to_index(Hash(h), Pos(pos))
+    }
+
+    /// Write the concatenation of `value_chunks` as the value for `key` into
+    /// this CDB, without concatenating them in memory first.
+    ///
+    /// The record written is identical to the one written by `put` with the
+    /// concatenated value.
+    pub fn put_chunks(&mut self, key: &[u8], value_chunks: &[&[u8]]) -> Result<()> {
+        let h = self.hasher.hash(key);
+        if self.strict && h == 0 {
+            return Err(Error::UnrepresentableKey);
+        }
+
+        let value_len: usize = value_chunks.iter().map(|chunk| chunk.len()).sum();
+        let pos = self.file.as_mut().unwrap().stream_position()?;
+        let header = self.record_header(pos, key.len() as u64, value_len as u64)?;
+        let file = self.file.as_mut().unwrap();
+        file.write_all(&header)?;
+
+        file.write_all(key)?;
+        for chunk in value_chunks {
+            file.write_all(chunk)?;
+        }
+        self.records_end = pos + header.len() as u64 + key.len() as u64 + value_len as u64;
+
+        self.add_to_index(Hash(h), Pos(pos))
+    }
+
+    /// Write `value` for `key` into this CDB, unless `key` was already
+    /// written. Returns whether the `value` was written.
+    ///
+    /// Records already written with the same hash as `key` are read back
+    /// from the `file` to compare their keys, so the `file` must be readable.
+    pub fn put_if_absent(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        if self.contains_key(key)? {
+            return Ok(false);
+        }
+        self.put(key, value)?;
+        Ok(true)
+    }
+
+    /// Get the [`Entry`](struct.Entry.html) for `key`, to insert a value for
+    /// it only if it hasn't been written yet.
+    ///
+    /// ```
+    /// use galvanize::Writer;
+    /// use std::io::Cursor;
+    ///
+    /// let mut c = Cursor::new(vec![]);
+    /// let mut cdb_writer = Writer::new(&mut c).unwrap();
+    /// assert!(cdb_writer.entry(b"key").unwrap().or_insert(b"first").unwrap());
+    /// assert!(!cdb_writer.entry(b"key").unwrap().or_insert(b"second").unwrap());
+    ///
+    /// let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    /// assert_eq!(cdb_reader.get(b"key"), vec![b"first".to_vec()]);
+    /// ```
+    pub fn entry<'w>(&'w mut self, key: &'w [u8]) -> Result<Entry<'w, 'a, F>> {
+        let occupied = self.contains_key(key)?;
+        Ok(Entry {
+            writer: self,
+            key,
+            occupied,
+        })
+    }
+
+    /// Whether a record for `key` has been written into this CDB.
+    fn contains_key(&mut self, key: &[u8]) -> Result<bool> {
+        let h = Hash(self.hasher.hash(key));
+        let (endianness, format) = (self.endianness, self.format);
+        let file = self.file.as_mut().unwrap();
+        let here = file.stream_position()?;
+        let mut found = false;
+        let mut buf = [0; 16];
+        let buf = &mut buf[..format.record_header_size()];
+        for &(_, Pos(pos)) in self.index[h.bucket()].iter().filter(|&&(rec_h, _)| rec_h == h) {
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(buf)?;
+            let (klen, _) = format.unpack_pair(endianness, buf);
+            if klen != key.len() as u64 {
+                continue;
+            }
+            let mut rec_key = vec![0; key.len()];
+            file.read_exact(&mut rec_key)?;
+            if rec_key == key {
+                found = true;
+                break;
+            }
+        }
+        // Leave the `file` where the next record has to be written.
+        file.seek(SeekFrom::Start(here))?;
+        Ok(found)
+    }
+
+    /// Write every record in `input`, in the format `cdbmake` reads and
+    /// `cdbdump` writes, into this CDB. Returns how many records were written.
+    ///
+    /// Each record is `+klen,dlen:key->value` followed by a newline, with the
+    /// `key` and `value` taken as exactly `klen` and `dlen` raw bytes, and the
+    /// records end with an empty line. If the `input` doesn't follow that
+    /// format, `Error::MalformedDump` is returned, and the records before the
+    /// problem are left written. The `input` is buffered, so bytes after the
+    /// empty line may be consumed.
+    ///
+    /// ```
+    /// use galvanize::{Reader, Writer};
+    /// use std::io::Cursor;
+    ///
+    /// let mut c = Cursor::new(vec![]);
+    /// let mut cdb_writer = Writer::new(&mut c).unwrap();
+    /// let loaded = cdb_writer.load_from(&mut &b"+3,5:key->value\n\n"[..]).unwrap();
+    /// assert_eq!(loaded, 1);
+    ///
+    /// let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    /// assert_eq!(cdb_reader.get(b"key"), vec![b"value".to_vec()]);
+    /// ```
+    pub fn load_from<R: Read>(&mut self, input: &mut R) -> Result<usize> {
+        let mut dump = DumpParser {
+            input: io::BufReader::new(input),
+            offset: 0,
+        };
+        let mut loaded = 0;
+        loop {
+            match dump.byte("`+` or an empty line")? {
+                b'\n' => return Ok(loaded),
+                b'+' => {}
+                _ => return Err(dump.malformed("`+` or an empty line")),
+            }
+            let klen = dump.length(b',')?;
+            let dlen = dump.length(b':')?;
+            let key = dump.bytes(klen, "the key")?;
+            dump.expect(b'-')?;
+            dump.expect(b'>')?;
+            let value = dump.bytes(dlen, "the value")?;
+            dump.expect(b'\n')?;
+            self.put(&key, &value)?;
+            loaded += 1;
+        }
+    }
 
-        let h = hash(key);
-        self.index[(h & 0xff) as usize].push((h, pos));
+    /// Write all the `(key, value)` `pairs` into this CDB, sorting them by
+    /// key first so that all the values for a key are next to each other in
+    /// the records section.
+    ///
+    /// The sort is stable, so the values for a key keep their relative order.
+    /// This only changes the physical layout of the records, lookups return
+    /// the same results as when using `put` for each pair in order. The
+    /// `pairs` are left sorted.
+    pub fn put_batch_sorted(&mut self, pairs: &mut [(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        for (key, value) in pairs.iter() {
+            self.put(key, value)?;
+        }
         Ok(())
     }
 
+    /// Write all the `(key, value)` pairs in `items` into this CDB, in order,
+    /// and return how many were written.
+    ///
+    /// Stops at the first error, leaving the pairs before it written.
+    ///
+    /// ```
+    /// use galvanize::Writer;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.insert("a", "1");
+    /// map.insert("b", "2");
+    /// let mut writer = Writer::in_memory().unwrap();
+    /// assert_eq!(writer.put_many(map).unwrap(), 2);
+    /// ```
+    pub fn put_many<I, K, V>(&mut self, items: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut written = 0;
+        for (key, value) in items {
+            self.put(key.as_ref(), value.as_ref())?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Write a `value_len` bytes long value for `key` into this CDB, streaming
+    /// it from `value` instead of holding it in memory.
+    ///
+    /// If `value` yields fewer or more than `value_len` bytes,
+    /// `Error::LengthMismatch` is returned. On that or any other error, the
+    /// partially written record isn't added to the hash tables, and the `file`
+    /// is rewound to its start, so the `Writer` can keep being used and the
+    /// next record overwrites it.
+    /// If nothing else is written, its bytes may be left past the end of the
+    /// finalized CDB.
+    pub fn put_stream<R: Read>(&mut self, key: &[u8], value_len: u32, value: &mut R) -> Result<()> {
+        let h = self.hasher.hash(key);
+        if self.strict && h == 0 {
+            return Err(Error::UnrepresentableKey);
+        }
+
+        let pos = self.file.as_mut().unwrap().stream_position()?;
+        let header = self.record_header(pos, key.len() as u64, u64::from(value_len))?;
+        let file = self.file.as_mut().unwrap();
+        let mut write_record = || -> Result<()> {
+            file.write_all(&header)?;
+            file.write_all(key)?;
+            let written = io::copy(&mut value.take(u64::from(value_len)), &mut **file)?;
+            if written != u64::from(value_len) || value.read(&mut [0])? != 0 {
+                return Err(Error::LengthMismatch {
+                    expected: value_len,
+                });
+            }
+            Ok(())
+        };
+        if let Err(e) = write_record() {
+            // Whatever was written of the record is overwritten by the next.
+            file.seek(SeekFrom::Start(pos))?;
+            return Err(e);
+        }
+        self.records_end = pos + header.len() as u64 + key.len() as u64 + u64::from(value_len);
+
+        self.add_to_index(Hash(h), Pos(pos))
+    }
+
+    /// Add the record written at `pos` with hash `h` to the `index`, and to
+    /// the `journal` if there's one.
+    fn add_to_index(&mut self, h: Hash, pos: Pos) -> Result<()> {
+        self.index[h.bucket()].push((h, pos));
+        if let Some(journal) = self.journal.as_mut() {
+            let mut entry = [0; 8];
+            entry[..4].copy_from_slice(&pack(h.0));
+            // Journals are only written for standard CDBs.
+            entry[4..].copy_from_slice(&pack(pos.0 as u32));
+            journal.file.write_all(&entry)?;
+        }
+        Ok(())
+    }
+
+    /// The key and value lengths of a record to be written at `pos`, as
+    /// written before its key.
+    fn record_header(&self, pos: u64, klen: u64, dlen: u64) -> Result<Vec<u8>> {
+        check_record(self.format, pos, klen, dlen)?;
+        let mut header = Vec::with_capacity(self.format.record_header_size());
+        self.format.pack_pair(self.endianness, klen, dlen, &mut header);
+        Ok(header)
+    }
+
+    /// Fraction of the finalized CDB that will be taken by the header and the
+    /// hash tables footer, as opposed to the records.
+    ///
+    /// The header size is fixed, so it dominates for CDBs with very few
+    /// records. This is purely informational, to help decide whether a CDB is
+    /// the right format for a given map.
+    pub fn overhead_ratio(&self) -> f64 {
+        // Every record takes two slots in the footer.
+        let footer: u64 = self
+            .index
+            .iter()
+            .map(|tbl| (tbl.len() * 2 * self.format.slot_size()) as u64)
+            .sum();
+        let header = self.format.header_size() as u64;
+        (header + footer) as f64 / (self.records_end + footer) as f64
+    }
+
     /// Write out the hash table to the `file` footer.
-    fn finalize(&mut self) {
-        let mut index: Vec<(u32, u32)> = Vec::new();
+    ///
+    /// The `file` is left in place, callers are expected to take it out
+    /// afterwards, whether this succeeded or not, so that `Drop` doesn't try
+    /// again.
+    fn finalize(&mut self) -> Result<()> {
+        let mut index: Vec<(u64, u64)> = Vec::new();
+        let (endianness, format) = (self.endianness, self.format);
 
         let file = if let Some(file) = self.file.as_mut() {
-            file.seek(SeekFrom::End(0)).unwrap();
             file
         } else {
-            return;
+            return Ok(());
         };
+        // Records rolled back by `put_stream` may be past the `records_end`.
+        let mut pos = file.seek(SeekFrom::Start(self.records_end))?;
+        check_tables(format, pos, &self.index)?;
+        // Each table is written at once, instead of one syscall per slot.
+        let mut buf: Vec<u8> = vec![];
         for tbl in &self.index {
-            let length = (tbl.len() << 1) as u32;
-            let mut ordered: Vec<(u32, u32)> = vec![(0, 0); length as usize];
-            for &pair in tbl {
-                let where_ = (pair.0 >> 8) % length;
-                for i in (where_..length).chain(0..where_) {
-                    if ordered[i as usize].0 == 0 {
-                        ordered[i as usize] = pair;
-                        break;
-                    }
-                }
-            }
-            index.push((
-                *file.seek(SeekFrom::End(0)).as_mut().unwrap() as u32,
-                length,
-            ));
-            for pair in ordered {
-                file.write_all(&pack(pair.0)).unwrap();
-                file.write_all(&pack(pair.1)).unwrap();
+            let ordered = hash_table(tbl, self.probing);
+            index.push((pos, ordered.len() as u64));
+            buf.clear();
+            for (Hash(h), Pos(rec_pos)) in ordered {
+                format.pack_pair(endianness, u64::from(h), rec_pos, &mut buf);
             }
+            file.write_all(&buf)?;
+            pos += buf.len() as u64;
         }
 
-        file.seek(SeekFrom::Start(0)).unwrap();
-        for pair in index {
-            file.write_all(&pack(pair.0)).unwrap();
-            file.write_all(&pack(pair.1)).unwrap();
+        buf.clear();
+        for (table_pos, nslots) in index {
+            format.pack_pair(endianness, table_pos, nslots, &mut buf);
         }
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&buf)?;
+        file.flush()?;
+
+        // The CDB is complete, there's nothing left to resume.
+        if let Some(journal) = self.journal.take() {
+            drop(journal.file);
+            let _ = fs::remove_file(journal.path);
+        }
+        Ok(())
+    }
+
+    /// Write out the hash tables footer and the header, like dropping the
+    /// `Writer` does, but reporting any error doing so.
+    ///
+    /// Dropping the `Writer` ignores errors, as there's no way to report
+    /// them, so use this when the CDB being complete matters.
+    pub fn close(mut self) -> Result<()> {
+        let result = self.finalize();
+        self.file = None;
+        result
+    }
+
+    /// Discard this `Writer` without writing out the hash tables footer.
+    ///
+    /// The `file` is left holding the header, which is all zeroes, and
+    /// whatever records were written, so it's not a valid CDB. The journal,
+    /// if any, is deleted as the build can't be resumed either.
+    pub fn abort(mut self) -> Result<()> {
+        // Without a `file`, `finalize` won't write anything on `Drop`.
+        self.file = None;
+        if let Some(journal) = self.journal.take() {
+            drop(journal.file);
+            fs::remove_file(journal.path)?;
+        }
+        Ok(())
+    }
+
+    /// Write out the hash table footer for the CDB in `file`, which contains
+    /// the 2048 bytes header followed by records, but no footer, like
+    /// [`finalize_existing`](#method.finalize_existing) does for a path.
+    ///
+    /// The records must be in the standard little endian `Format::Cdb32`
+    /// layout, and keys are placed with the DJB hash. A record running past
+    /// the end of the `file` returns `Error::CorruptRecord`, and errors
+    /// writing the footer are returned, like with [`close`](#method.close).
+    pub fn finalize_records(file: &'a mut F) -> Result<()> {
+        let end = file.seek(SeekFrom::End(0))?;
+        if end < HEADER_SIZE as u64 {
+            return Err(Error::CDBTooSmall { size: end });
+        }
+
+        let mut index: Vec<Vec<(Hash, Pos)>> = vec![Vec::new(); 256];
+        let mut pos = file.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        let mut buf = [0; RECORD_HEADER_SIZE];
+        while pos < end {
+            // The lengths aren't trusted until the record fits in the file.
+            let corrupt = Error::CorruptRecord { position: pos };
+            if pos + RECORD_HEADER_SIZE as u64 > end {
+                return Err(corrupt);
+            }
+            file.read_exact(&mut buf)?;
+            let klen = unpack([buf[0], buf[1], buf[2], buf[3]]);
+            let dlen = unpack([buf[4], buf[5], buf[6], buf[7]]);
+            if pos + (RECORD_HEADER_SIZE as u64) + u64::from(klen) + u64::from(dlen) > end {
+                return Err(corrupt);
+            }
+
+            let mut key = vec![0; klen as usize];
+            file.read_exact(&mut key)?;
+            let h = Hash(hash(&key));
+            index[h.bucket()].push((h, Pos(pos)));
+
+            // Skip over the value, we only need the keys to build the index.
+            pos = file.seek(SeekFrom::Current(i64::from(dlen)))?;
+        }
+
+        let writer =
+            Writer::from_handle(Handle::Borrowed(file), index, Endianness::Little, Format::Cdb32)?;
+        writer.close()
     }
 
     /// Transform this `Writer` into a `Reader` using the same underlying
@@ -122,18 +704,725 @@ impl<'a, F: Write + Read + Seek + 'a> Writer<'a, F> {
     ///
     /// The `Writer` will flush the hash table to the underlying `file`.
     pub fn as_reader(mut self) -> Result<Reader<'a, F>> {
-        {
-            let s = &mut self;
-            s.finalize();
+        let finalized = self.finalize();
+        let file = self.file.take().unwrap();
+        finalized?;
+        let mut reader = Reader::from_handle(file, self.endianness, self.format)?;
+        reader.set_probing(self.probing);
+        reader.set_hasher(self.hasher);
+        Ok(reader)
+    }
+
+    /// Transform this `Writer` into a `Reader` like `as_reader` does, and
+    /// check that the records written can be found through it.
+    ///
+    /// Every hash table is read back to check that it holds all of its
+    /// records, and a sample of the keys written, along with any key whose
+    /// hash is `0`, are looked up. If any of these checks fail,
+    /// `Error::CorruptTable` is returned for the affected bucket. This costs
+    /// an extra read of the hash tables and of the sampled records, so it's
+    /// an opt in sanity check for the end of a build.
+    pub fn finish_verified(self) -> Result<Reader<'a, F>> {
+        let counts: Vec<usize> = self.index.iter().map(Vec::len).collect();
+        let step = (self.len() / 256).max(1);
+        let sample: Vec<(Hash, Pos)> = self
+            .index
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|&(i, &(h, _))| i % step == 0 || h == Hash(0))
+            .map(|(_, &entry)| entry)
+            .collect();
+
+        let mut reader = self.as_reader()?;
+        for (bucket, &count) in counts.iter().enumerate() {
+            let info = reader.table_info(bucket as u8)?;
+            if info.slots as usize != count << 1 || info.occupied as usize != count {
+                return Err(Error::CorruptTable { bucket });
+            }
+        }
+        for (h, Pos(pos)) in sample {
+            let key = reader.key_at(pos)?;
+            if reader.count(&key)? == 0 {
+                return Err(Error::CorruptTable {
+                    bucket: h.bucket(),
+                });
+            }
         }
+        Ok(reader)
+    }
+}
+
+impl Writer<'static, Cursor<Vec<u8>>> {
+    /// Creates a new `Writer` that builds the CDB in memory, to be retrieved
+    /// with [`into_bytes`](#method.into_bytes).
+    pub fn in_memory() -> Result<Writer<'static, Cursor<Vec<u8>>>> {
+        let mut file = Cursor::new(vec![0; HEADER_SIZE]);
+        file.set_position(HEADER_SIZE as u64);
+
+        let index = vec![Vec::new(); 256];
+        Writer::from_handle(Handle::Owned(file), index, Endianness::Little, Format::Cdb32)
+    }
+}
+
+impl<'a> Writer<'a, Cursor<Vec<u8>>> {
+    /// Write out the hash tables and return the complete CDB.
+    ///
+    /// If the `Writer` was created over a borrowed `Cursor`, its buffer is
+    /// taken out of it, leaving it empty.
+    ///
+    /// ```
+    /// use galvanize::{Reader, Writer};
+    ///
+    /// let mut cdb_writer = Writer::in_memory().unwrap();
+    /// cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+    /// let bytes = cdb_writer.into_bytes().unwrap();
+    ///
+    /// let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    /// assert_eq!(cdb_reader.get("key".as_bytes()), vec!["value".as_bytes()]);
+    /// ```
+    pub fn into_bytes(mut self) -> Result<Vec<u8>> {
+        let finalized = self.finalize();
         let file = self.file.take().unwrap();
-        Reader::new(file)
+        finalized?;
+        Ok(match file {
+            Handle::Owned(file) => file.into_inner(),
+            Handle::Borrowed(file) => mem::take(file.get_mut()),
+        })
+    }
+}
+
+impl Writer<'static, File> {
+    /// Opens the CDB at `path` to add more records to it, rebuilding the
+    /// index of the records already in it by scanning the records section.
+    ///
+    /// Unlike [`Reader::as_writer`](../reader/struct.Reader.html#method.as_writer),
+    /// the hash tables footer isn't read, only the header to find where the
+    /// records end, so this works even when the footer is damaged. Every
+    /// record is read and its key hashed again though, so `as_writer`, which
+    /// only reads the footer, is faster and remains the default way to
+    /// append, see `benches/append.rs`.
+    ///
+    /// The footer is truncated and regenerated when the `Writer` is closed.
+    pub fn append<P: AsRef<Path>>(path: P) -> Result<Writer<'static, File>> {
+        Self::append_with_hasher(path, &DjbHasher)
+    }
+
+    /// Opens the CDB at `path` for appending like [`append`](#method.append),
+    /// for a CDB whose keys were hashed with `hasher`.
+    pub fn append_with_hasher<P: AsRef<Path>>(
+        path: P,
+        hasher: &'static dyn Hasher,
+    ) -> Result<Writer<'static, File>> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut index: Vec<Vec<(Hash, Pos)>> = vec![Vec::new(); 256];
+        let table_start = {
+            let mut reader = Reader::new(&mut file)?;
+            let sizes = reader.size_breakdown();
+            let mut pos = sizes.header;
+            reader.for_each_record(|key, value| -> Result<()> {
+                let h = Hash(hasher.hash(key));
+                index[h.bucket()].push((h, Pos(pos)));
+                pos += (RECORD_HEADER_SIZE + key.len() + value.len()) as u64;
+                Ok(())
+            })?;
+            if pos != sizes.header + sizes.records {
+                return Err(Error::CorruptRecord { position: pos });
+            }
+            pos
+        };
+        file.set_len(table_start)?;
+        // New records go where the footer was, not after a hole.
+        file.seek(SeekFrom::Start(table_start))?;
+        let mut writer =
+            Writer::from_handle(Handle::Owned(file), index, Endianness::Little, Format::Cdb32)?;
+        writer.hasher = hasher;
+        Ok(writer)
+    }
+
+    /// Creates a new [`AtomicWriter`](struct.AtomicWriter.html) that builds
+    /// the CDB in a temporary file next to `final_path`, and only replaces
+    /// `final_path` with it once it's complete.
+    pub fn atomic<P: AsRef<Path>>(final_path: P) -> Result<AtomicWriter> {
+        let final_path = final_path.as_ref().to_path_buf();
+        let mut temp_name = final_path.file_name().unwrap_or_default().to_os_string();
+        let id = ATOMIC_WRITERS.fetch_add(1, Ordering::Relaxed);
+        temp_name.push(format!(".tmp{}.{}", std::process::id(), id));
+        let temp_path = final_path.with_file_name(temp_name);
+
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        file.write_all(&[0; HEADER_SIZE])?;
+        let index = vec![Vec::new(); 256];
+        let writer =
+            Writer::from_handle(Handle::Owned(file), index, Endianness::Little, Format::Cdb32)?;
+        Ok(AtomicWriter {
+            writer: Some(writer),
+            temp_path,
+            final_path,
+        })
+    }
+}
+
+impl<'a> Writer<'a, File> {
+    /// Discard this `Writer` like [`abort`](#method.abort) does, and also
+    /// truncate the `file` back to empty so nothing of the build is left.
+    pub fn abort_and_truncate(mut self) -> Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        self.abort()
+    }
+
+    /// Write out the hash table footer for an existing file at `path` that
+    /// contains the 2048 bytes header followed by records, but no footer.
+    ///
+    /// The records are scanned to rebuild the index, and the footer and
+    /// header pointers are written the same way as when a `Writer` is
+    /// dropped. This is the equivalent of `cdbmake`'s finalize step, and
+    /// allows the records to be written by a different tool or process.
+    pub fn finalize_existing<P: AsRef<Path>>(path: P) -> Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        Writer::finalize_records(&mut file)
+    }
+
+    /// Continue an interrupted build of the CDB in `file`, that was started
+    /// with [`Writer::with_journal`](#method.with_journal) using the same
+    /// `journal_path`.
+    ///
+    /// The index is rebuilt from the journal, and anything in the `file`
+    /// after the last journaled record, like a partially written record, is
+    /// truncated. A record whose journal entry didn't make it to disk is
+    /// lost, so use [`len`](#method.len) to find out how many records were
+    /// recovered and where to continue from.
+    pub fn resume<P: AsRef<Path>>(file: &'a mut File, journal_path: P) -> Result<Writer<'a, File>> {
+        let path = journal_path.as_ref().to_path_buf();
+        let mut journal = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut entries = vec![];
+        journal.read_to_end(&mut entries)?;
+
+        let mut index: Vec<Vec<(Hash, Pos)>> = vec![Vec::new(); 256];
+        let mut last = None;
+        // A partially written entry at the end of the journal is dropped.
+        for entry in entries.chunks_exact(8) {
+            let h = Hash(unpack([entry[0], entry[1], entry[2], entry[3]]));
+            let pos = unpack([entry[4], entry[5], entry[6], entry[7]]);
+            index[h.bucket()].push((h, Pos(u64::from(pos))));
+            last = Some(pos);
+        }
+        let journaled = (entries.len() / 8 * 8) as u64;
+
+        let file_len = file.seek(SeekFrom::End(0))?;
+        if file_len < HEADER_SIZE as u64 {
+            return Err(Error::CDBTooSmall { size: file_len });
+        }
+        let end = match last {
+            Some(pos) => {
+                let mut buf = [0; RECORD_HEADER_SIZE];
+                file.seek(SeekFrom::Start(u64::from(pos)))?;
+                file.read_exact(&mut buf)?;
+                let klen = unpack([buf[0], buf[1], buf[2], buf[3]]);
+                let dlen = unpack([buf[4], buf[5], buf[6], buf[7]]);
+                u64::from(pos) + RECORD_HEADER_SIZE as u64 + u64::from(klen) + u64::from(dlen)
+            }
+            None => HEADER_SIZE as u64,
+        };
+        if end > file_len {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the last journaled record extends past the end of the file",
+            )));
+        }
+
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+        journal.set_len(journaled)?;
+        journal.seek(SeekFrom::Start(journaled))?;
+
+        let handle = Handle::Borrowed(file);
+        let mut writer = Writer::from_handle(handle, index, Endianness::Little, Format::Cdb32)?;
+        writer.journal = Some(Journal {
+            file: journal,
+            path,
+        });
+        Ok(writer)
+    }
+}
+
+// `write_empty` doesn't use the `Writer`'s own `file`, so it only lives on a
+// concrete `Writer` for `Writer::write_empty` to be callable as is.
+impl Writer<'static, File> {
+    /// Write the smallest valid CDB, one without any records, into `w`.
+    ///
+    /// The result is a 2048 bytes header where all 256 hash tables are empty,
+    /// which is useful as a placeholder for code paths that need a valid CDB
+    /// before the real data is available.
+    ///
+    /// `w` isn't truncated: if it held a longer CDB, its bytes past the
+    /// header are left in place. Readers ignore them, but a `File` can be
+    /// shrunk with `set_len(2048)` afterwards.
+    pub fn write_empty<W: Write + Seek>(w: &mut W) -> Result<()> {
+        w.seek(SeekFrom::Start(0))?;
+        for _ in 0..256 {
+            // Empty tables start right after the header.
+            w.write_all(&pack(HEADER_SIZE as u32))?;
+            w.write_all(&pack(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the records out of a dump in the `cdbmake` format, keeping track of
+/// the `offset` to report where the problems are.
+struct DumpParser<R: Read> {
+    input: io::BufReader<R>,
+    /// How many bytes of the dump were consumed.
+    offset: u64,
+}
+
+impl<R: Read> DumpParser<R> {
+    /// The error for finding something other than `expected` at `offset`.
+    fn malformed(&self, expected: &'static str) -> Error {
+        Error::MalformedDump {
+            offset: self.offset.saturating_sub(1),
+            expected,
+        }
+    }
+
+    /// Read the next byte, which is `expected` to be there.
+    fn byte(&mut self, expected: &'static str) -> Result<u8> {
+        let mut buf = [0];
+        match self.input.read(&mut buf)? {
+            0 => {
+                // The error points right past the end of the dump.
+                self.offset += 1;
+                Err(self.malformed(expected))
+            }
+            _ => {
+                self.offset += 1;
+                Ok(buf[0])
+            }
+        }
+    }
+
+    /// Read the next byte, which must be `expected`.
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        let description = match expected {
+            b'-' | b'>' => "`->`",
+            _ => "a newline",
+        };
+        if self.byte(description)? != expected {
+            return Err(self.malformed(description));
+        }
+        Ok(())
+    }
+
+    /// Read a decimal length, terminated by `end`.
+    fn length(&mut self, end: u8) -> Result<u64> {
+        let (mut length, mut digits) = (0u64, 0);
+        loop {
+            match self.byte("a length")? {
+                b @ b'0'..=b'9' => {
+                    length = length
+                        .checked_mul(10)
+                        .and_then(|l| l.checked_add(u64::from(b - b'0')))
+                        .ok_or_else(|| self.malformed("a length that fits in 64 bits"))?;
+                    digits += 1;
+                }
+                b if b == end && digits > 0 => return Ok(length),
+                _ => return Err(self.malformed("a length")),
+            }
+        }
+    }
+
+    /// Read exactly `len` raw bytes.
+    fn bytes(&mut self, len: u64, expected: &'static str) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        // Reading through `take` doesn't trust `len` with the allocation.
+        let read = self.input.by_ref().take(len).read_to_end(&mut buf)? as u64;
+        self.offset += read;
+        if read != len {
+            self.offset += 1;
+            return Err(self.malformed(expected));
+        }
+        Ok(buf)
+    }
+}
+
+/// Check that a record with a `klen` bytes key and a `dlen` bytes value can
+/// be written at `pos` in a CDB of the given `format`.
+///
+/// If either length, or the position where the record ends, doesn't fit in
+/// the `format`, `Error::RecordTooLarge` is returned.
+fn check_record(format: Format, pos: u64, klen: u64, dlen: u64) -> Result<()> {
+    let max = format.max_value();
+    let end = pos
+        .saturating_add(format.record_header_size() as u64)
+        .saturating_add(klen)
+        .saturating_add(dlen);
+    for &size in &[klen, dlen, end] {
+        if size > max {
+            return Err(Error::RecordTooLarge { size });
+        }
+    }
+    Ok(())
+}
+
+/// Check that every hash table of `index` starts at a position that fits in
+/// `format`, when the tables are written back to back from `start`.
+fn check_tables(format: Format, start: u64, index: &[Vec<(Hash, Pos)>]) -> Result<()> {
+    let max = format.max_value();
+    let mut pos = start;
+    for tbl in index {
+        if pos > max {
+            return Err(Error::CDBTooBig { size: pos });
+        }
+        // Every table has twice as many slots as entries.
+        pos = pos.saturating_add(2 * tbl.len() as u64 * format.slot_size() as u64);
+    }
+    Ok(())
+}
+
+/// Lay out the `(hash, position)` entries of a bucket into the slots of its
+/// hash table, which has twice as many slots as entries.
+fn hash_table(entries: &[(Hash, Pos)], probing: &dyn ProbeSequence) -> Vec<(Hash, Pos)> {
+    let length = (entries.len() << 1) as u32;
+    let mut ordered: Vec<(Hash, Pos)> = vec![(Hash(0), Pos(0)); length as usize];
+    for &pair in entries {
+        let where_ = pair.0.slot(length);
+        for attempt in 0..length {
+            let i = probing.slot(where_, attempt, length);
+            if ordered[i as usize].1 == Pos(0) {
+                ordered[i as usize] = pair;
+                break;
+            }
+        }
+    }
+    ordered
+}
+
+/// Creates a CDB in memory, and writes it out to a `sink` that doesn't need
+/// to be seekable, like a socket or a compressing stream.
+///
+/// A `Writer` needs to seek back to the start of its `file` to write the
+/// header once all records are known. Instead, a `BufferedWriter` holds all
+/// the records in memory, and writes the whole CDB in order on
+/// [`finish`](#method.finish). This trades memory for the ability to write to
+/// any `Write` sink.
+///
+/// #Example
+///
+/// ```
+/// use galvanize::{BufferedWriter, Reader};
+///
+/// let mut cdb_writer = BufferedWriter::new(vec![]);
+/// cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+/// let bytes = cdb_writer.finish().unwrap();
+///
+/// let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+/// assert_eq!(cdb_reader.get("key".as_bytes()), vec!["value".as_bytes()]);
+/// ```
+#[derive(Debug)]
+pub struct BufferedWriter<W: Write> {
+    /// Where the CDB is written to on `finish`.
+    sink: W,
+    /// Records section of the CDB.
+    records: Vec<u8>,
+    /// Working hash table for the contents of the CDB.
+    index: Vec<Vec<(Hash, Pos)>>,
+    /// Hash function used to place keys in the hash tables.
+    hasher: &'static dyn Hasher,
+}
+
+impl<W: Write> BufferedWriter<W> {
+    /// Creates a new `BufferedWriter` that will write the CDB to `sink`.
+    pub fn new(sink: W) -> BufferedWriter<W> {
+        Self::with_hasher(sink, &DjbHasher)
+    }
+
+    /// Creates a new `BufferedWriter` that will write the CDB to `sink`,
+    /// hashing keys with `hasher` like
+    /// [`Writer::with_hasher`](struct.Writer.html#method.with_hasher) does.
+    pub fn with_hasher(sink: W, hasher: &'static dyn Hasher) -> BufferedWriter<W> {
+        BufferedWriter {
+            sink,
+            records: vec![],
+            index: vec![Vec::new(); 256],
+            hasher,
+        }
+    }
+
+    /// Write `value` for `key` into this CDB.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let h = Hash(self.hasher.hash(key));
+        let pos = Pos((HEADER_SIZE + self.records.len()) as u64);
+        check_record(Format::Cdb32, pos.0, key.len() as u64, value.len() as u64)?;
+        self.records.extend_from_slice(&pack(key.len() as u32));
+        self.records.extend_from_slice(&pack(value.len() as u32));
+        self.records.extend_from_slice(key);
+        self.records.extend_from_slice(value);
+        self.index[h.bucket()].push((h, pos));
+        Ok(())
+    }
+
+    /// How many records have been written into this CDB.
+    pub fn len(&self) -> usize {
+        self.index.iter().map(Vec::len).sum()
+    }
+
+    /// Whether no records have been written into this CDB.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the whole CDB out to the `sink`, header first, and return it.
+    pub fn finish(mut self) -> Result<W> {
+        let tables: Vec<Vec<(Hash, Pos)>> = self
+            .index
+            .iter()
+            .map(|tbl| hash_table(tbl, &LinearProbe))
+            .collect();
+
+        let mut pos = (HEADER_SIZE + self.records.len()) as u64;
+        check_tables(Format::Cdb32, pos, &self.index)?;
+        let mut buf: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+        for tbl in &tables {
+            buf.extend_from_slice(&pack(pos as u32));
+            buf.extend_from_slice(&pack(tbl.len() as u32));
+            pos += (tbl.len() * SLOT_SIZE) as u64;
+        }
+        self.sink.write_all(&buf)?;
+        self.sink.write_all(&self.records)?;
+        for tbl in tables {
+            buf.clear();
+            for (Hash(h), Pos(pos)) in tbl {
+                buf.extend_from_slice(&pack(h));
+                buf.extend_from_slice(&pack(pos as u32));
+            }
+            self.sink.write_all(&buf)?;
+        }
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+}
+
+/// Size in bytes of the CDB that writing records with the given `entries`
+/// `(key length, value length)` would produce, without writing anything.
+///
+/// Every record takes 8 bytes plus its key and value, and two hash table
+/// slots of 8 bytes each, on top of the 2048 bytes header. This is exact for
+/// CDBs written by this crate, but other tools may size the hash tables
+/// differently, so treat it as an estimate. A result over `u32::MAX` means
+/// the records won't fit in a CDB.
+///
+/// ```
+/// use galvanize::writer::estimate_size;
+///
+/// assert_eq!(estimate_size(vec![(3, 5)]), 2048 + 8 + 3 + 5 + 16);
+/// ```
+pub fn estimate_size<I: IntoIterator<Item = (usize, usize)>>(entries: I) -> u64 {
+    entries
+        .into_iter()
+        .map(|(klen, dlen)| (RECORD_HEADER_SIZE + 2 * SLOT_SIZE) as u64 + klen as u64 + dlen as u64)
+        .sum::<u64>()
+        + HEADER_SIZE as u64
+}
+
+/// Copy every `(key, value)` pair in each of the `shards` into `out`, and
+/// return how many pairs were copied.
+///
+/// Records are streamed one at a time, so memory use is bounded by the
+/// largest record regardless of the size of the shards. Duplicated keys,
+/// both within and across shards, are all kept in `out`. A corrupt record in
+/// any of the `shards` stops the merge with an error.
+pub fn combine_shards<'a, R, W>(
+    shards: &mut [&mut Reader<'a, R>],
+    out: &mut Writer<W>,
+) -> Result<usize>
+where
+    R: Read + Seek + 'a,
+    W: Write + Read + Seek,
+{
+    let mut count = 0;
+    for shard in shards.iter_mut() {
+        shard.for_each_record(|k, v| {
+            out.put(k, v)?;
+            count += 1;
+            Ok::<(), Error>(())
+        })?;
+    }
+    Ok(count)
+}
+
+/// Copy every `(key, value)` pair in `src` whose key isn't in `deletes` into
+/// `dst`, and return how many pairs were copied.
+///
+/// A CDB can't be changed in place, so this is a full rewrite of `src`, which
+/// is how deletions take effect for stores built on top of CDBs. All values
+/// of a surviving key are copied, in their original order. A corrupt record
+/// in `src` stops the rewrite with an error.
+pub fn rewrite_with_deletes<'a, R, W>(
+    src: &mut Reader<'a, R>,
+    deletes: &HashSet<Vec<u8>>,
+    dst: &mut Writer<W>,
+) -> Result<usize>
+where
+    R: Read + Seek + 'a,
+    W: Write + Read + Seek,
+{
+    let mut count = 0;
+    src.for_each_record(|k, v| {
+        if !deletes.contains(k) {
+            dst.put(k, v)?;
+            count += 1;
+        }
+        Ok::<(), Error>(())
+    })?;
+    Ok(count)
+}
+
+/// Copy every `(key, value)` pair in the standard CDB `src` into `dst`, which
+/// must have been created with `Writer::new_with_format` and
+/// `Format::Cdb64`, and return how many pairs were copied. Any other `dst`
+/// returns an `Error::IOError` of kind `InvalidInput`, and nothing is copied.
+///
+/// This is the migration path for CDBs about to outgrow the 4GB limit.
+/// Records are streamed one at a time, in their original order.
+///
+/// ```
+/// use galvanize::writer::convert_to_cdb64;
+/// use galvanize::{Format, Reader, Writer};
+/// use std::io::Cursor;
+///
+/// let mut src = Reader::open("tests/testdata/top250pws.cdb").unwrap();
+/// let mut c = Cursor::new(vec![]);
+/// let mut dst = Writer::new_with_format(&mut c, Format::Cdb64).unwrap();
+/// assert_eq!(convert_to_cdb64(&mut src, &mut dst).unwrap(), 250);
+/// ```
+pub fn convert_to_cdb64<'a, R, W>(src: &mut Reader<'a, R>, dst: &mut Writer<W>) -> Result<usize>
+where
+    R: Read + Seek + 'a,
+    W: Write + Read + Seek,
+{
+    if dst.format != Format::Cdb64 {
+        return Err(Error::IOError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "convert_to_cdb64 needs a Writer created with Format::Cdb64",
+        )));
+    }
+    let mut count = 0;
+    src.for_each_record(|k, v| {
+        dst.put(k, v)?;
+        count += 1;
+        Ok::<(), Error>(())
+    })?;
+    Ok(count)
+}
+
+/// Builds a CDB in a temporary file, replacing the destination with it in a
+/// single `rename` once it's complete, returned by
+/// [`Writer::atomic`](struct.Writer.html#method.atomic).
+///
+/// Readers of the destination see either the old CDB or the new one, never a
+/// partially written one, even if the process or the system crashes. All of
+/// the `Writer` methods are available through `Deref`. If the `AtomicWriter`
+/// is dropped without calling [`commit`](#method.commit), the temporary file
+/// is deleted and the destination is left untouched.
+///
+/// ```
+/// use galvanize::{Reader, Writer};
+///
+/// let mut cdb_writer = Writer::atomic("atomic_example.cdb").unwrap();
+/// cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+/// cdb_writer.commit().unwrap();
+///
+/// let mut cdb_reader = Reader::open("atomic_example.cdb").unwrap();
+/// assert_eq!(cdb_reader.get("key".as_bytes()), vec!["value".as_bytes()]);
+/// # std::fs::remove_file("atomic_example.cdb").unwrap();
+/// ```
+pub struct AtomicWriter {
+    /// Writer for the temporary file, only `None` once committed.
+    writer: Option<Writer<'static, File>>,
+    /// Where the CDB is built.
+    temp_path: PathBuf,
+    /// Where the CDB ends up on `commit`.
+    final_path: PathBuf,
+}
+
+impl AtomicWriter {
+    /// Write out the hash tables, sync the temporary file to disk, and
+    /// rename it over the destination. On unix, the directory is synced too,
+    /// so the rename itself survives a crash.
+    ///
+    /// On error the destination is left untouched and the temporary file is
+    /// deleted.
+    pub fn commit(mut self) -> Result<()> {
+        let mut writer = self.writer.take().unwrap();
+        let finalized = writer.finalize();
+        let file = writer.file.take();
+        finalized?;
+        if let Some(file) = file {
+            file.sync_all()?;
+        }
+        fs::rename(&self.temp_path, &self.final_path)?;
+        #[cfg(unix)]
+        {
+            let parent = match self.final_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for AtomicWriter {
+    type Target = Writer<'static, File>;
+
+    fn deref(&self) -> &Self::Target {
+        self.writer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for AtomicWriter {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.writer.as_mut().unwrap()
+    }
+}
+
+impl Drop for AtomicWriter {
+    /// Delete the temporary file, unless it was renamed by `commit`.
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.abort();
+        }
+        if self.temp_path.exists() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+impl<'a, F: Write + Read + Seek + 'a> Extend<(Vec<u8>, Vec<u8>)> for Writer<'a, F> {
+    /// Write all the `(key, value)` pairs in `iter` into this CDB.
+    ///
+    /// # Panics
+    ///
+    /// If writing a pair fails, use [`put_many`](struct.Writer.html#method.put_many)
+    /// to handle the error instead.
+    fn extend<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(&mut self, iter: I) {
+        if let Err(e) = self.put_many(iter) {
+            panic!("could not write to the CDB: {}", e);
+        }
     }
 }
 
 impl<'a, F: Write + Read + Seek + 'a> Drop for Writer<'a, F> {
     /// Write out the hash table footer for this CDB.
+    ///
+    /// Errors are ignored, use [`Writer::close`](struct.Writer.html#method.close)
+    /// to find out about them.
     fn drop(&mut self) {
-        self.finalize();
+        let _ = self.finalize();
     }
 }
@@ -1,14 +1,39 @@
 extern crate galvanize;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
-use galvanize::helpers::hash;
+use galvanize::helpers::{
+    decode_key, hash, pack, vec2str, vec2str_with, DjbHasher, Hasher, LinearProbe, ProbeSequence,
+    HEADER_SIZE, RECORD_HEADER_SIZE, SLOT_SIZE,
+};
+use galvanize::BufferedWriter;
+use galvanize::CachingReader;
+use galvanize::Endianness;
+use galvanize::Error;
+use galvanize::Format;
+use galvanize::LookupProfile;
+use galvanize::ValueHandle;
+use galvanize::{NormalizingReader, NormalizingWriter};
+use galvanize::types::Anomaly;
 use galvanize::Reader;
+use galvanize::RwCdb;
+use galvanize::CdbRead;
+use galvanize::SharedReader;
+use galvanize::Stats;
+use galvanize::writer::{combine_shards, convert_to_cdb64, estimate_size, rewrite_with_deletes};
 use galvanize::Writer;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
 use std::path::Path;
+use std::thread;
 
 #[test]
 fn known_good_djb_hash() {
@@ -233,3 +258,2449 @@ fn turn_reader_into_writer() {
         assert_eq!(v2, &cdb_reader.get_from_pos(k2, 0).unwrap()[..]);
     }
 }
+
+#[test]
+fn too_small_file_reports_its_size() {
+    let mut c = Cursor::new(vec![0; 1000]);
+    match Reader::new(&mut c) {
+        Err(Error::CDBTooSmall { size }) => assert_eq!(size, 1000),
+        other => panic!("Expected CDBTooSmall, got {:?}", other),
+    }
+}
+
+#[test]
+fn finalize_externally_written_records() {
+    let filename = "finalize_existing.cdb";
+    let items = [
+        ("key".as_bytes(), "value".as_bytes()),
+        ("another key".as_bytes(), "value field".as_bytes()),
+        ("key".as_bytes(), "second value".as_bytes()),
+    ];
+    {
+        // Write the header and records by hand, without the footer.
+        let mut f = File::create(filename).unwrap();
+        f.write_all(&[0; 2048]).unwrap();
+        for &(k, v) in items.iter() {
+            f.write_all(&pack(k.len() as u32)).unwrap();
+            f.write_all(&pack(v.len() as u32)).unwrap();
+            f.write_all(k).unwrap();
+            f.write_all(v).unwrap();
+        }
+    }
+    Writer::finalize_existing(filename).unwrap();
+
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    assert_eq!(cdb_reader.len(), 3);
+    assert_eq!(
+        cdb_reader.get("key".as_bytes()),
+        vec!["value".as_bytes(), "second value".as_bytes()]
+    );
+    assert_eq!(
+        cdb_reader.get_first("another key".as_bytes()).unwrap(),
+        "value field".as_bytes()
+    );
+}
+
+#[test]
+fn lazily_get_values() {
+    let filename = "tests/testdata/pwdump.cdb";
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let key = "f7396427246008f9d580c9a666000976".as_bytes();
+
+    assert_eq!(cdb_reader.get_values(key).count(), 3);
+    assert_eq!(
+        cdb_reader.get_values(key).nth(2).unwrap().unwrap(),
+        "DEFTONES".as_bytes()
+    );
+    assert!(cdb_reader.get_values(key).nth(3).is_none());
+    assert!(cdb_reader
+        .get_values("non_existing_key".as_bytes())
+        .next()
+        .is_none());
+
+    let values: Vec<Vec<u8>> = cdb_reader.get_iter(key).map(|v| v.unwrap()).collect();
+    assert_eq!(values, cdb_reader.get(key));
+    assert!(cdb_reader.get_iter(b"non_existing_key").next().is_none());
+}
+
+#[test]
+fn write_empty_cdb() {
+    let mut c = Cursor::new(vec![]);
+    Writer::write_empty(&mut c).unwrap();
+    assert_eq!(c.get_ref().len(), 2048);
+
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.len(), 0);
+    assert!(cdb_reader.is_empty());
+    assert!(cdb_reader.get("key".as_bytes()).is_empty());
+    assert_eq!(cdb_reader.into_iter().count(), 0);
+
+    // Writing over a longer CDB leaves its records past the header, unread.
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+    }
+    Writer::write_empty(&mut c).unwrap();
+    assert!(c.get_ref().len() > 2048);
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert!(cdb_reader.is_empty());
+    assert!(cdb_reader.get(b"key").is_empty());
+    assert_eq!(cdb_reader.into_iter().count(), 0);
+}
+
+#[test]
+fn all_zero_header_is_an_empty_cdb() {
+    let mut cdb_reader = Reader::from_bytes(vec![0; HEADER_SIZE]).unwrap();
+    assert!(cdb_reader.is_empty());
+    let sizes = cdb_reader.size_breakdown();
+    assert_eq!((sizes.header, sizes.records, sizes.footer), (2048, 0, 0));
+    assert!(cdb_reader.get(b"key").is_empty());
+    assert_eq!(cdb_reader.lookup(b"key").unwrap(), None);
+    assert_eq!((&mut cdb_reader).into_iter().count(), 0);
+    assert!(cdb_reader.verify().unwrap().is_ok());
+
+    // A table can't start inside the header, even an empty one.
+    let mut header = vec![0; HEADER_SIZE];
+    header[8..12].copy_from_slice(&pack(1024));
+    match Reader::from_bytes(header) {
+        Err(Error::CorruptTable { bucket: 1 }) => {}
+        other => panic!("Expected CorruptTable, got {:?}", other.map(|r| r.len())),
+    }
+}
+
+#[test]
+fn format_size_constants() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+    }
+    // One record, in a hash table with two slots.
+    let record = RECORD_HEADER_SIZE + 3 + 5;
+    assert_eq!(c.get_ref().len(), HEADER_SIZE + record + 2 * SLOT_SIZE);
+    let header = &c.get_ref()[HEADER_SIZE..HEADER_SIZE + RECORD_HEADER_SIZE];
+    assert_eq!(header, &[3, 0, 0, 0, 5, 0, 0, 0]);
+}
+
+#[test]
+fn strict_writer_rejects_zero_hash_keys() {
+    // This key's DJB hash is `0`, which older readers took for an empty slot.
+    let key = b"\x97\xc3aue";
+    assert_eq!(hash(key), 0);
+
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.set_strict(true);
+    match cdb_writer.put(key, "value".as_bytes()) {
+        Err(Error::UnrepresentableKey) => (),
+        other => panic!("Expected UnrepresentableKey, got {:?}", other),
+    }
+    cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+
+    let cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.len(), 1);
+}
+
+#[test]
+fn bounded_lookup() {
+    let filename = "tests/testdata/top250pws.cdb";
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let key = "letmein".as_bytes();
+
+    assert_eq!(
+        cdb_reader.get_first_bounded(key, 250).unwrap(),
+        "10".as_bytes()
+    );
+    match cdb_reader.get_first_bounded(key, 0) {
+        Err(Error::ProbeBudgetExceeded) => (),
+        other => panic!("Expected ProbeBudgetExceeded, got {:?}", other),
+    }
+    match cdb_reader.get_first_bounded("non_existing_key".as_bytes(), 250) {
+        Err(Error::KeyNotInCDB) => (),
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+}
+
+#[test]
+fn combine_multiple_shards() {
+    let mut f1 = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut f2 = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut r1 = Reader::new(&mut f1).unwrap();
+    let mut r2 = Reader::new(&mut f2).unwrap();
+
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    let count = combine_shards(&mut [&mut r1, &mut r2], &mut cdb_writer).unwrap();
+    assert_eq!(count, 3250);
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.len(), 3250);
+    assert_eq!(cdb_reader.get("letmein".as_bytes()), vec!["10".as_bytes()]);
+    assert_eq!(
+        cdb_reader.get("f7396427246008f9d580c9a666000976".as_bytes()),
+        vec![
+            "defton".as_bytes(),
+            "deftones".as_bytes(),
+            "DEFTONES".as_bytes(),
+        ]
+    );
+}
+
+#[test]
+fn combine_shards_with_a_truncated_shard() {
+    let mut shards = vec![vec![], vec![]];
+    File::open("tests/testdata/top250pws.cdb")
+        .unwrap()
+        .read_to_end(&mut shards[0])
+        .unwrap();
+    File::open("tests/testdata/pwdump.cdb")
+        .unwrap()
+        .read_to_end(&mut shards[1])
+        .unwrap();
+    // Cut the second shard halfway through its records.
+    let bytes = &mut shards[1];
+    let table_start = galvanize::helpers::unpack([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    bytes.truncate((2048 + table_start as usize) / 2);
+    let mut r2 = Reader::from_bytes(shards.pop().unwrap()).unwrap();
+    let mut r1 = Reader::from_bytes(shards.pop().unwrap()).unwrap();
+
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    assert!(combine_shards(&mut [&mut r1, &mut r2], &mut cdb_writer).is_err());
+}
+
+#[test]
+fn clone_in_memory_reader() {
+    let mut bytes = vec![];
+    File::open("tests/testdata/top250pws.cdb")
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    let cdb_reader = Reader::from_bytes(bytes).unwrap();
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let mut cdb_reader = cdb_reader.clone();
+            thread::spawn(move || {
+                let iter = cdb_reader.clone();
+                let mut count = 0;
+                for (k, v) in iter.into_iter() {
+                    // Lookups on one clone don't disturb iteration on another.
+                    assert_eq!(cdb_reader.get_first(&k).unwrap(), v);
+                    count += 1;
+                }
+                count
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 250);
+    }
+}
+
+#[test]
+fn read_big_endian_file() {
+    let filename = "tests/testdata/bigendian.cdb";
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::with_endianness(&mut f, Endianness::Big).unwrap();
+
+    assert_eq!(cdb_reader.len(), 104);
+    assert_eq!(
+        cdb_reader.get("one".as_bytes()),
+        vec!["1".as_bytes(), "uno".as_bytes()]
+    );
+    assert_eq!(
+        cdb_reader.get_first("key42".as_bytes()).unwrap(),
+        "value42".as_bytes()
+    );
+    assert_eq!(cdb_reader.into_iter().count(), 104);
+}
+
+#[test]
+fn big_endian_round_trip() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::with_endianness(&mut c, Endianness::Big).unwrap();
+        for i in 0..100 {
+            let s = i.to_string();
+            cdb_writer.put(s.as_bytes(), s.as_bytes()).unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::with_endianness(&mut c, Endianness::Big).unwrap();
+    assert_eq!(cdb_reader.len(), 100);
+    for i in 0..100 {
+        let s = i.to_string();
+        assert_eq!(cdb_reader.get_first(s.as_bytes()).unwrap(), s.as_bytes());
+    }
+}
+
+#[test]
+fn memory_usage() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let file_reader = Reader::new(&mut f).unwrap();
+    assert!(file_reader.memory_usage() >= 4096);
+    assert!(file_reader.memory_usage() < 8192);
+
+    let mut bytes = vec![];
+    File::open("tests/testdata/top250pws.cdb")
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    let len = bytes.len();
+    let memory_reader = Reader::from_bytes(bytes).unwrap();
+    assert!(memory_reader.memory_usage() >= len + 2048);
+    assert_eq!(memory_reader.clone().memory_usage(), memory_reader.memory_usage());
+}
+
+#[test]
+fn find_linear_with_corrupt_footer() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put("key".as_bytes(), "a".as_bytes()).unwrap();
+        cdb_writer.put("other".as_bytes(), "b".as_bytes()).unwrap();
+        cdb_writer.put("key".as_bytes(), "c".as_bytes()).unwrap();
+    }
+    // Clobber the hash tables, keeping the header and the records.
+    let records_end = 2048 + 3 * 8 + 3 + 5 + 3 + 3;
+    let mut bytes = c.into_inner();
+    for b in bytes[records_end..].iter_mut() {
+        *b = 0xff;
+    }
+
+    let mut c = Cursor::new(bytes);
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert!(cdb_reader.get("key".as_bytes()).is_empty());
+    assert_eq!(
+        cdb_reader.find_linear("key".as_bytes()),
+        vec!["a".as_bytes(), "c".as_bytes()]
+    );
+}
+
+#[test]
+fn verify() {
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let report = cdb_reader.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.anomalies);
+    assert_eq!(report.records, 3000);
+    assert_eq!(report.total_bytes, 195651);
+
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+        cdb_writer.put("other".as_bytes(), "value".as_bytes()).unwrap();
+    }
+    // Make the second record's value overrun the records section.
+    let mut bytes = c.into_inner();
+    let second = 2048 + 8 + 3 + 5;
+    bytes[second + 4..second + 8].copy_from_slice(&pack(100));
+
+    let mut c = Cursor::new(bytes);
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    let report = cdb_reader.verify().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.records, 1);
+    assert_eq!(
+        report.anomalies,
+        vec![
+            Anomaly::RecordOutOfBounds {
+                position: second as u64
+            },
+            Anomaly::RecordCountMismatch {
+                records: 1,
+                expected: 2
+            },
+        ]
+    );
+}
+
+#[test]
+fn put_prehashed() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    for i in 0..100 {
+        let key = i.to_string();
+        let h = hash(key.as_bytes());
+        cdb_writer
+            .put_prehashed(key.as_bytes(), h, "value".as_bytes())
+            .unwrap();
+    }
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.len(), 100);
+    for i in 0..100 {
+        let key = i.to_string();
+        assert_eq!(
+            cdb_reader.get_first(key.as_bytes()).unwrap(),
+            "value".as_bytes()
+        );
+    }
+}
+
+#[test]
+fn caching_reader() {
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let cdb_reader = Reader::new(&mut f).unwrap();
+    let mut cache = CachingReader::new(cdb_reader, 2);
+    let key = "f7396427246008f9d580c9a666000976".as_bytes();
+    let values = vec![
+        "defton".as_bytes(),
+        "deftones".as_bytes(),
+        "DEFTONES".as_bytes(),
+    ];
+
+    for _ in 0..3 {
+        assert_eq!(cache.get(key), values);
+        assert_eq!(cache.get_first(key).unwrap(), "defton".as_bytes());
+        // Missing keys are cached too, and evict each other before `key`.
+        assert!(cache.get("a".as_bytes()).is_empty());
+        assert!(cache.get_first("b".as_bytes()).is_err());
+    }
+
+    let mut uncached = CachingReader::new(cache.into_inner(), 0);
+    assert_eq!(uncached.get(key), values);
+    assert_eq!(uncached.get_first(key).unwrap(), "defton".as_bytes());
+}
+
+#[test]
+fn read_only_reader_into_writer() {
+    let filename = "read_only_into_writer.cdb";
+    {
+        let mut f = File::create(filename).unwrap();
+        let _ = make_writer(&mut f, &[("key".as_bytes(), "value".as_bytes())]);
+    }
+    let len = fs::metadata(filename).unwrap().len();
+
+    let mut f = File::open(filename).unwrap();
+    let cdb_reader = Reader::new(&mut f).unwrap();
+    match cdb_reader.as_writer() {
+        Err(Error::FileNotWritable(_)) => (),
+        Err(e) => panic!("Expected FileNotWritable, got {:?}", e),
+        Ok(_) => panic!("Expected FileNotWritable"),
+    }
+    // The footer must be left untouched.
+    assert_eq!(fs::metadata(filename).unwrap().len(), len);
+}
+
+#[test]
+fn size_breakdown() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+    cdb_writer.put("other".as_bytes(), "value".as_bytes()).unwrap();
+    let cdb_reader = cdb_writer.as_reader().unwrap();
+
+    let breakdown = cdb_reader.size_breakdown();
+    assert_eq!(breakdown.header, 2048);
+    assert_eq!(breakdown.records, 2 * 8 + 3 + 5 + 5 + 5);
+    // Two slots of 8 bytes for each record.
+    assert_eq!(breakdown.footer, 2 * 2 * 8);
+    assert_eq!(breakdown.total(), cdb_reader.total_size());
+}
+
+#[test]
+fn writer_overhead_ratio() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    assert_eq!(cdb_writer.overhead_ratio(), 1.0);
+
+    cdb_writer.put("key".as_bytes(), &[0; 2037]).unwrap();
+    // 2048 header bytes and 16 footer bytes, out of 4112.
+    assert_eq!(cdb_writer.overhead_ratio(), 2064.0 / 4112.0);
+
+    let cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.total_size(), 4112);
+}
+
+#[test]
+fn key_iter() {
+    let filename = "tests/testdata/pwdump.cdb";
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+
+    let keys: Vec<Vec<u8>> = (&mut cdb_reader).into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys.len(), 3000);
+    assert_eq!(cdb_reader.key_iter().collect::<Vec<_>>(), keys);
+}
+
+#[test]
+fn values_in_file_order() {
+    let filename = "tests/testdata/pwdump.cdb";
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+
+    let values: Vec<Vec<u8>> = (&mut cdb_reader).into_iter().map(|(_, v)| v).collect();
+    assert_eq!(values.len(), 3000);
+    assert_eq!(cdb_reader.values(), values);
+    assert_eq!(cdb_reader.values_iter().collect::<Vec<_>>(), values);
+}
+
+#[test]
+fn reader_from_slice() {
+    let mut bytes = vec![];
+    File::open("tests/testdata/pwdump.cdb")
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    let cdb_reader = Reader::from_slice(&bytes).unwrap();
+    assert_eq!(cdb_reader.len(), 3000);
+    assert_eq!(cdb_reader.into_iter().count(), 3000);
+
+    match Reader::try_from(&bytes[..100]) {
+        Err(Error::CDBTooSmall { size }) => assert_eq!(size, 100),
+        other => panic!("Expected CDBTooSmall, got {:?}", other),
+    }
+}
+
+#[test]
+fn profile_lookups() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let keys = cdb_reader.keys();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+
+    let profile = cdb_reader.profile_lookups(&keys).unwrap();
+    assert_eq!(profile.lookups, 250);
+    assert!(profile.min_probes >= 1);
+    assert!(profile.min_probes <= profile.p99_probes);
+    assert!(profile.p99_probes <= profile.max_probes);
+    assert!(profile.mean_probes >= f64::from(profile.min_probes));
+    assert!(profile.mean_probes <= f64::from(profile.max_probes));
+    assert!(profile.over_two_accesses >= 0.0 && profile.over_two_accesses < 1.0);
+
+    assert_eq!(
+        cdb_reader.profile_lookups(&[]).unwrap(),
+        LookupProfile::default()
+    );
+}
+
+#[test]
+fn put_stream() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    let value = vec![42; 100_000];
+    cdb_writer
+        .put_stream("key".as_bytes(), 100_000, &mut &value[..])
+        .unwrap();
+    match cdb_writer.put_stream("short".as_bytes(), 10, &mut &value[..5]) {
+        Err(Error::LengthMismatch { expected: 10 }) => (),
+        other => panic!("Expected LengthMismatch, got {:?}", other),
+    }
+    match cdb_writer.put_stream("long".as_bytes(), 10, &mut &value[..11]) {
+        Err(Error::LengthMismatch { expected: 10 }) => (),
+        other => panic!("Expected LengthMismatch, got {:?}", other),
+    }
+    // A `value` that fails partway through.
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken stream"))
+        }
+    }
+    match cdb_writer.put_stream("broken".as_bytes(), 10, &mut (&value[..5]).chain(Broken)) {
+        Err(Error::IOError(_)) => (),
+        other => panic!("Expected IOError, got {:?}", other),
+    }
+    // The partial records are overwritten by the next one.
+    cdb_writer.put(b"after", b"1").unwrap();
+    match cdb_writer.put_stream("partial".as_bytes(), 10, &mut &value[..5]) {
+        Err(Error::LengthMismatch { expected: 10 }) => (),
+        other => panic!("Expected LengthMismatch, got {:?}", other),
+    }
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.get_first("key".as_bytes()).unwrap(), value);
+    assert!(cdb_reader.get("short".as_bytes()).is_empty());
+    assert!(cdb_reader.get("long".as_bytes()).is_empty());
+    assert!(cdb_reader.get("partial".as_bytes()).is_empty());
+    assert!(cdb_reader.get("broken".as_bytes()).is_empty());
+    let keys: Vec<Vec<u8>> = (&mut cdb_reader).into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"key".to_vec(), b"after".to_vec()]);
+    assert!(cdb_reader.verify().unwrap().is_ok());
+}
+
+#[test]
+fn reopen_replaced_file() {
+    let filename = "reopen.cdb";
+    let tmp_filename = "reopen.cdb.tmp";
+    {
+        let mut f = File::create(filename).unwrap();
+        let _ = make_writer(&mut f, &[("key".as_bytes(), "old".as_bytes())]);
+    }
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    assert_eq!(cdb_reader.get_first("key".as_bytes()).unwrap(), "old".as_bytes());
+
+    {
+        let mut f = File::create(tmp_filename).unwrap();
+        let _ = make_writer(
+            &mut f,
+            &[
+                ("key".as_bytes(), "new".as_bytes()),
+                ("other".as_bytes(), "value".as_bytes()),
+            ],
+        );
+    }
+    fs::rename(tmp_filename, filename).unwrap();
+    // Still reading from the old file.
+    assert_eq!(cdb_reader.get_first("key".as_bytes()).unwrap(), "old".as_bytes());
+
+    cdb_reader.reopen(filename).unwrap();
+    assert_eq!(cdb_reader.len(), 2);
+    assert_eq!(cdb_reader.get_first("key".as_bytes()).unwrap(), "new".as_bytes());
+
+    assert!(cdb_reader.reopen("non_existing.cdb").is_err());
+    assert_eq!(cdb_reader.len(), 2);
+
+    // A reader for a CDB written with another hash function keeps using it.
+    #[derive(Debug)]
+    struct Reversed;
+    impl Hasher for Reversed {
+        fn hash(&self, key: &[u8]) -> u32 {
+            hash(key).reverse_bits()
+        }
+    }
+    let write = |value: &[u8]| {
+        let mut f = File::create(tmp_filename).unwrap();
+        let mut cdb_writer = Writer::with_hasher(&mut f, &Reversed).unwrap();
+        for i in 0..50 {
+            cdb_writer.put(format!("key{}", i).as_bytes(), value).unwrap();
+        }
+        cdb_writer.close().unwrap();
+        fs::rename(tmp_filename, filename).unwrap();
+    };
+    write(b"old");
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::with_hasher(&mut f, &Reversed).unwrap();
+    assert_eq!(cdb_reader.get_first(b"key7").unwrap(), b"old");
+    write(b"new");
+    cdb_reader.reopen(filename).unwrap();
+    for i in 0..50 {
+        assert_eq!(cdb_reader.get_first(format!("key{}", i).as_bytes()).unwrap(), b"new");
+    }
+    let _ = fs::remove_file(filename);
+}
+
+#[test]
+fn empty_and_non_empty_values_under_the_same_key() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    let key = "key".as_bytes();
+    cdb_writer.put(key, "".as_bytes()).unwrap();
+    cdb_writer.put(key, "data".as_bytes()).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.get(key), vec!["".as_bytes(), "data".as_bytes()]);
+    assert_eq!(cdb_reader.get_from_pos(key, 0).unwrap(), "".as_bytes());
+    assert_eq!(cdb_reader.get_from_pos(key, 1).unwrap(), "data".as_bytes());
+    assert_eq!(cdb_reader.get_first(key).unwrap(), "".as_bytes());
+}
+
+#[test]
+fn table_info() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put("key".as_bytes(), "a".as_bytes()).unwrap();
+    cdb_writer.put("key".as_bytes(), "b".as_bytes()).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    let bucket = (hash("key".as_bytes()) & 0xff) as u8;
+    let info = cdb_reader.table_info(bucket).unwrap();
+    assert_eq!(info.slots, 4);
+    assert_eq!(info.occupied, 2);
+    let info = cdb_reader.table_info(bucket.wrapping_add(1)).unwrap();
+    assert_eq!(info.slots, 0);
+    assert_eq!(info.occupied, 0);
+}
+
+#[cfg(feature = "arrayvec")]
+#[test]
+fn get_first_small() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put("flag".as_bytes(), "on".as_bytes()).unwrap();
+    cdb_writer.put("token".as_bytes(), "0123456789".as_bytes()).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    let value = cdb_reader.get_first_small::<8>("flag".as_bytes()).unwrap();
+    assert_eq!(&value[..], "on".as_bytes());
+    match cdb_reader.get_first_small::<8>("token".as_bytes()) {
+        Err(Error::EntryTooLarge { size, capacity }) => {
+            assert_eq!(size, 10);
+            assert_eq!(capacity, 8);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    let value = cdb_reader.get_first_small::<10>("token".as_bytes()).unwrap();
+    assert_eq!(&value[..], "0123456789".as_bytes());
+    match cdb_reader.get_first_small::<8>("missing".as_bytes()) {
+        Err(Error::KeyNotInCDB) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn count_values_under_key() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    for i in 0..5 {
+        cdb_writer.put("many".as_bytes(), format!("{}", i).as_bytes()).unwrap();
+    }
+    cdb_writer.put("one".as_bytes(), "1".as_bytes()).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.count("many".as_bytes()).unwrap(), 5);
+    assert_eq!(cdb_reader.count("one".as_bytes()).unwrap(), 1);
+    assert_eq!(cdb_reader.count("none".as_bytes()).unwrap(), 0);
+    assert_eq!(cdb_reader.count_values("many".as_bytes()).unwrap(), 5);
+    assert_eq!(cdb_reader.count_values("one".as_bytes()).unwrap(), 1);
+    assert_eq!(cdb_reader.count_values("none".as_bytes()).unwrap(), 0);
+}
+
+#[test]
+fn table_inside_header_is_corrupt() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+    }
+    // Point the only non-empty table into the header.
+    let mut bytes = c.into_inner();
+    let bucket = (hash("key".as_bytes()) & 0xff) as usize;
+    bytes[bucket * 8..bucket * 8 + 4].copy_from_slice(&pack(100));
+    match Reader::from_bytes(bytes) {
+        Err(Error::CorruptTable { bucket: b }) => assert_eq!(b, bucket),
+        other => panic!("Expected CorruptTable, got {:?}", other.map(|r| r.len())),
+    }
+}
+
+#[test]
+fn resume_from_journal() {
+    let filename = "resume.cdb";
+    let journal = "resume.cdb.journal";
+    {
+        let mut f = File::create(filename).unwrap();
+        let mut cdb_writer = Writer::with_journal(&mut f, journal).unwrap();
+        for i in 0..3 {
+            let k = format!("key{}", i);
+            cdb_writer.put(k.as_bytes(), "value".as_bytes()).unwrap();
+        }
+        // Simulate a crash: the footer is never written.
+        std::mem::forget(cdb_writer);
+    }
+    // A partially written record and journal entry.
+    let mut f = OpenOptions::new().append(true).open(filename).unwrap();
+    f.write_all(&pack(3)).unwrap();
+    let mut j = OpenOptions::new().append(true).open(journal).unwrap();
+    j.write_all(&[1, 2, 3]).unwrap();
+
+    {
+        let mut f = OpenOptions::new().read(true).write(true).open(filename).unwrap();
+        let mut cdb_writer = Writer::resume(&mut f, journal).unwrap();
+        assert_eq!(cdb_writer.len(), 3);
+        cdb_writer.put("key3".as_bytes(), "value".as_bytes()).unwrap();
+    }
+    assert!(!Path::new(journal).exists());
+
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    assert_eq!(cdb_reader.len(), 4);
+    assert!(cdb_reader.verify().unwrap().is_ok());
+    for i in 0..4 {
+        let k = format!("key{}", i);
+        assert_eq!(cdb_reader.get(k.as_bytes()), vec!["value".as_bytes()]);
+    }
+}
+
+#[test]
+fn occurrence_out_of_range() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put("key".as_bytes(), "a".as_bytes()).unwrap();
+    cdb_writer.put("key".as_bytes(), "b".as_bytes()).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.get_from_pos("key".as_bytes(), 1).unwrap(), "b".as_bytes());
+    match cdb_reader.get_from_pos("key".as_bytes(), 2) {
+        Err(Error::OccurrenceOutOfRange { available }) => assert_eq!(available, 2),
+        other => panic!("Expected OccurrenceOutOfRange, got {:?}", other),
+    }
+    match cdb_reader.get_from_pos("other".as_bytes(), 0) {
+        Err(Error::KeyNotInCDB) => (),
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+    assert_eq!(cdb_reader.get("key".as_bytes()).len(), 2);
+}
+
+#[test]
+fn bucket_records() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    for i in 0..=255u8 {
+        cdb_writer.put(&[i], &[i]).unwrap();
+        cdb_writer.put(&[i, i], &[i, i]).unwrap();
+    }
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    let mut total = 0;
+    for bucket in 0..=255u8 {
+        let records = cdb_reader.bucket_records(bucket).unwrap();
+        for (k, v) in &records {
+            assert_eq!(hash(k) & 0xff, u32::from(bucket));
+            assert_eq!(k, v);
+        }
+        total += records.len();
+    }
+    assert_eq!(total, 512);
+}
+
+#[test]
+fn embedded_at_offset() {
+    let container_header = [0xaa; 100];
+    let mut c = Cursor::new(container_header.to_vec());
+    {
+        let mut cdb_writer = Writer::new_at(&mut c, 100).unwrap();
+        cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+        cdb_writer.put("other".as_bytes(), "data".as_bytes()).unwrap();
+    }
+    let len = c.get_ref().len() as u64 - 100;
+    {
+        let mut cdb_reader = Reader::new_at(&mut c, 100, len).unwrap();
+        assert_eq!(cdb_reader.len(), 2);
+        assert_eq!(cdb_reader.get("key".as_bytes()), vec!["value".as_bytes()]);
+        assert_eq!(cdb_reader.get("other".as_bytes()), vec!["data".as_bytes()]);
+    }
+
+    // The CDB is self contained after the container's header.
+    let mut bytes = c.into_inner();
+    assert_eq!(&bytes[..100], &container_header[..]);
+    let mut cdb_reader = Reader::from_slice(&bytes[100..]).unwrap();
+    assert_eq!(cdb_reader.get("key".as_bytes()), vec!["value".as_bytes()]);
+    let sizes = cdb_reader.size_breakdown();
+
+    // Container data after the CDB isn't counted as part of its footer.
+    bytes.extend_from_slice(&[0xbb; 50]);
+    let mut c = Cursor::new(bytes);
+    let mut cdb_reader = Reader::new_at(&mut c, 100, len).unwrap();
+    assert_eq!(cdb_reader.total_size(), len);
+    assert_eq!(cdb_reader.size_breakdown(), sizes);
+    assert_eq!(cdb_reader.get("other".as_bytes()), vec!["data".as_bytes()]);
+    let report = cdb_reader.verify().unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.total_bytes, len);
+}
+
+#[test]
+fn single_slot_table() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+    }
+    // Shrink the key's table to a single slot pointing at its record, as
+    // other tools are allowed to write it.
+    let mut bytes = c.into_inner();
+    let h = hash("key".as_bytes());
+    let bucket = (h & 0xff) as usize;
+    let table = galvanize::helpers::unpack([
+        bytes[bucket * 8],
+        bytes[bucket * 8 + 1],
+        bytes[bucket * 8 + 2],
+        bytes[bucket * 8 + 3],
+    ]) as usize;
+    bytes[bucket * 8 + 4..bucket * 8 + 8].copy_from_slice(&pack(1));
+    bytes[table..table + 4].copy_from_slice(&pack(h));
+    bytes[table + 4..table + 8].copy_from_slice(&pack(2048));
+    bytes[table + 8..table + 16].copy_from_slice(&[0; 8]);
+
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    assert_eq!(cdb_reader.get_first("key".as_bytes()).unwrap(), "value".as_bytes());
+    assert_eq!(cdb_reader.get("key".as_bytes()).len(), 1);
+}
+
+#[test]
+fn iter_from_offset() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let all: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+
+    let offset = {
+        let mut items = (&mut cdb_reader).into_iter();
+        for item in all.iter().take(100) {
+            assert_eq!(&items.next().unwrap(), item);
+        }
+        items.offset().unwrap()
+    };
+    let rest: Vec<(Vec<u8>, Vec<u8>)> = cdb_reader.iter_from(offset).unwrap().collect();
+    assert_eq!(&rest[..], &all[100..]);
+
+    match cdb_reader.iter_from(100) {
+        Err(Error::InvalidOffset { offset }) => assert_eq!(offset, 100),
+        Err(e) => panic!("Expected InvalidOffset, got {:?}", e),
+        Ok(_) => panic!("Expected InvalidOffset"),
+    }
+    assert!(cdb_reader.iter_from(u64::MAX).is_err());
+}
+
+#[test]
+fn sample() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let all: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+
+    let sample = cdb_reader.sample(10, 42);
+    assert_eq!(sample.len(), 10);
+    for item in &sample {
+        assert!(all.contains(item));
+    }
+    assert_eq!(cdb_reader.sample(10, 42), sample);
+    assert_ne!(cdb_reader.sample(10, 7), sample);
+    assert_eq!(cdb_reader.sample(1000, 42), all);
+    assert!(cdb_reader.sample(0, 42).is_empty());
+}
+
+#[test]
+fn iteration_stops_at_corrupt_record() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put("key".as_bytes(), "value".as_bytes()).unwrap();
+        cdb_writer.put("other".as_bytes(), "value".as_bytes()).unwrap();
+    }
+    // Make the second record's value overrun into the hash tables.
+    let mut bytes = c.into_inner();
+    let second = 2048 + 8 + 3 + 5;
+    bytes[second + 4..second + 8].copy_from_slice(&pack(100));
+
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+    assert_eq!(items, vec![("key".as_bytes().to_vec(), "value".as_bytes().to_vec())]);
+    assert_eq!(cdb_reader.keys(), vec!["key".as_bytes().to_vec()]);
+
+    let mut items = cdb_reader.try_iter();
+    assert_eq!(items.next().unwrap().unwrap().0, "key".as_bytes());
+    match items.next() {
+        Some(Err(Error::CorruptRecord { position })) => assert_eq!(position, second as u64),
+        other => panic!("Expected CorruptRecord, got {:?}", other),
+    }
+    assert!(items.next().is_none());
+}
+
+#[test]
+fn put_batch_sorted() {
+    let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        ("b".as_bytes().to_vec(), "1".as_bytes().to_vec()),
+        ("a".as_bytes().to_vec(), "2".as_bytes().to_vec()),
+        ("b".as_bytes().to_vec(), "3".as_bytes().to_vec()),
+        ("c".as_bytes().to_vec(), "4".as_bytes().to_vec()),
+        ("a".as_bytes().to_vec(), "5".as_bytes().to_vec()),
+    ];
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put_batch_sorted(&mut pairs).unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    let keys: Vec<Vec<u8>> = cdb_reader.keys();
+    let expected: Vec<&[u8]> = vec![b"a", b"a", b"b", b"b", b"c"];
+    assert_eq!(keys, expected);
+    assert_eq!(cdb_reader.get(b"a"), vec![b"2".to_vec(), b"5".to_vec()]);
+    assert_eq!(cdb_reader.get(b"b"), vec![b"1".to_vec(), b"3".to_vec()]);
+}
+
+#[test]
+fn get_first_with_count() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put(b"password", b"primary").unwrap();
+    cdb_writer.put(b"other", b"value").unwrap();
+    cdb_writer.put(b"password", b"variant").unwrap();
+    cdb_writer.put(b"password", b"another").unwrap();
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    let (value, count) = cdb_reader.get_first_with_count(b"password").unwrap();
+    assert_eq!(value, b"primary");
+    assert_eq!(count, 3);
+    assert_eq!(cdb_reader.get_first_with_count(b"other").unwrap(), (b"value".to_vec(), 1));
+    match cdb_reader.get_first_with_count(b"missing") {
+        Err(Error::KeyNotInCDB) => (),
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+}
+
+#[test]
+fn for_each_record() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let all: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+
+    let mut seen = vec![];
+    cdb_reader
+        .for_each_record(|k, v| -> Result<(), Error> {
+            seen.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(seen, all);
+
+    // Errors from the closure stop the scan.
+    let mut count = 0;
+    let result = cdb_reader.for_each_record(|_, _| {
+        count += 1;
+        if count == 3 {
+            Err(Error::KeyNotInCDB)
+        } else {
+            Ok(())
+        }
+    });
+    match result {
+        Err(Error::KeyNotInCDB) => assert_eq!(count, 3),
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+}
+
+#[test]
+fn overlapping_tables_are_corrupt() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.put(b"other", b"value").unwrap();
+    }
+    let bytes = c.into_inner();
+    let key_bucket = (hash(b"key") & 0xff) as usize;
+    let other_bucket = (hash(b"other") & 0xff) as usize;
+    assert_ne!(key_bucket, other_bucket);
+    assert!(Reader::from_bytes(bytes.clone()).is_ok());
+
+    // Make both tables start at the same position.
+    let mut overlapping = bytes.clone();
+    let other_pos = overlapping[other_bucket * 8..other_bucket * 8 + 4].to_vec();
+    overlapping[key_bucket * 8..key_bucket * 8 + 4].copy_from_slice(&other_pos);
+    match Reader::from_bytes(overlapping) {
+        Err(Error::CorruptTable { .. }) => (),
+        other => panic!("Expected CorruptTable, got {:?}", other.map(|r| r.len())),
+    }
+
+    // Point an empty table into the records, which would end them early.
+    let mut inside_records = bytes;
+    let empty = (0..256).find(|&b| b != key_bucket && b != other_bucket).unwrap();
+    inside_records[empty * 8..empty * 8 + 4].copy_from_slice(&pack(2060));
+    match Reader::from_bytes(inside_records) {
+        Err(Error::CorruptTable { .. }) => (),
+        other => panic!("Expected CorruptTable, got {:?}", other.map(|r| r.len())),
+    }
+}
+
+fn lowercase(key: &[u8]) -> Vec<u8> {
+    key.to_ascii_lowercase()
+}
+
+#[test]
+fn normalized_keys() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = NormalizingWriter::new(Writer::new(&mut c).unwrap(), lowercase);
+        cdb_writer.put(b"Someone@Example.com", b"1").unwrap();
+        cdb_writer.put(b"SOMEONE@example.com", b"2").unwrap();
+    }
+
+    let mut cdb_reader = NormalizingReader::new(Reader::new(&mut c).unwrap(), lowercase);
+    assert_eq!(cdb_reader.get(b"someone@EXAMPLE.com"), vec![b"1", b"2"]);
+    assert_eq!(cdb_reader.get_first(b"someone@example.com").unwrap(), b"1");
+    assert_eq!(cdb_reader.get_from_pos(b"Someone@Example.Com", 1).unwrap(), b"2");
+    assert_eq!(cdb_reader.count(b"SomeOne@example.com").unwrap(), 2);
+
+    // The keys are stored normalized.
+    let mut cdb_reader = cdb_reader.into_inner();
+    assert_eq!(cdb_reader.get(b"someone@example.com").len(), 2);
+    assert!(cdb_reader.get(b"Someone@Example.com").is_empty());
+}
+
+#[test]
+fn finish_verified() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    for i in 0..1000 {
+        let k = format!("key{}", i);
+        cdb_writer.put(k.as_bytes(), b"value").unwrap();
+    }
+    let cdb_reader = cdb_writer.finish_verified().unwrap();
+    assert_eq!(cdb_reader.len(), 1000);
+
+    // This key's DJB hash is `0`, it's always sampled and must be found.
+    let key = b"\x97\xc3aue";
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    cdb_writer.put(key, b"value").unwrap();
+    let mut cdb_reader = cdb_writer.finish_verified().unwrap();
+    assert_eq!(cdb_reader.get_first(key).unwrap(), b"value");
+}
+
+#[test]
+fn buffered_writer_matches_writer() {
+    let mut c = Cursor::new(vec![]);
+    let mut buffered = BufferedWriter::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..500 {
+            let k = format!("key{}", i % 300);
+            let v = format!("value{}", i);
+            cdb_writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+            buffered.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+    }
+    assert_eq!(buffered.len(), 500);
+    assert_eq!(buffered.finish().unwrap(), c.into_inner());
+}
+
+#[test]
+fn sorted_iter() {
+    let pairs: Vec<(&[u8], &[u8])> = vec![
+        (b"b", b"1"),
+        (b"a", b"2"),
+        (b"c", b"3"),
+        (b"b", b"4"),
+    ];
+    let mut forward = Cursor::new(vec![]);
+    let mut backward = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut forward).unwrap();
+        for &(k, v) in &pairs {
+            cdb_writer.put(k, v).unwrap();
+        }
+        let mut cdb_writer = Writer::new(&mut backward).unwrap();
+        for &(k, v) in pairs.iter().rev() {
+            cdb_writer.put(k, v).unwrap();
+        }
+    }
+
+    let mut cdb_reader = Reader::new(&mut forward).unwrap();
+    let sorted: Vec<(Vec<u8>, Vec<u8>)> = cdb_reader.sorted_iter().unwrap().collect();
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        (b"a".to_vec(), b"2".to_vec()),
+        (b"b".to_vec(), b"1".to_vec()),
+        (b"b".to_vec(), b"4".to_vec()),
+        (b"c".to_vec(), b"3".to_vec()),
+    ];
+    assert_eq!(sorted, expected);
+
+    let mut cdb_reader = Reader::new(&mut backward).unwrap();
+    let keys: Vec<Vec<u8>> = cdb_reader.sorted_iter().unwrap().map(|(k, _)| k).collect();
+    let expected: Vec<&[u8]> = vec![b"a", b"b", b"b", b"c"];
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn put_chunks() {
+    let mut chunked = Cursor::new(vec![]);
+    let mut whole = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut chunked).unwrap();
+        cdb_writer.put_chunks(b"key", &[b"pre", b"", b"body", b"suf"]).unwrap();
+        cdb_writer.put_chunks(b"empty", &[]).unwrap();
+        let mut cdb_writer = Writer::new(&mut whole).unwrap();
+        cdb_writer.put(b"key", b"prebodysuf").unwrap();
+        cdb_writer.put(b"empty", b"").unwrap();
+    }
+    assert_eq!(chunked.get_ref(), whole.get_ref());
+}
+
+#[test]
+fn estimate_size_matches_written_size() {
+    let pairs: Vec<(String, String)> = (0..300)
+        .map(|i| (format!("key{}", i % 200), format!("value{}", i)))
+        .collect();
+    let estimate = estimate_size(pairs.iter().map(|(k, v)| (k.len(), v.len())));
+
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for (k, v) in &pairs {
+            cdb_writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+    }
+    assert_eq!(estimate, c.get_ref().len() as u64);
+    assert_eq!(estimate_size(vec![]), 2048);
+}
+
+#[test]
+fn contains_value() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    // Values are the rank of each password, from 1 to 250.
+    assert!(cdb_reader.contains_value(b"1"));
+    assert!(cdb_reader.contains_value(b"250"));
+    assert!(!cdb_reader.contains_value(b"251"));
+    assert!(!cdb_reader.contains_value(b"letmein"));
+    assert!(!cdb_reader.contains_value(b""));
+}
+
+#[test]
+fn shared_reader_across_threads() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..100 {
+            let key = format!("key{}", i % 50);
+            cdb_writer.put(key.as_bytes(), format!("value{}", i).as_bytes()).unwrap();
+        }
+    }
+    let cdb_reader = std::sync::Arc::new(SharedReader::new(c.into_inner()).unwrap());
+    assert_eq!(cdb_reader.len(), 100);
+
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let shared = std::sync::Arc::clone(&cdb_reader);
+            thread::spawn(move || {
+                for i in (t..50).step_by(4) {
+                    let key = format!("key{}", i);
+                    let expected = vec![
+                        format!("value{}", i).into_bytes(),
+                        format!("value{}", i + 50).into_bytes(),
+                    ];
+                    assert_eq!(shared.get(key.as_bytes()), expected);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(cdb_reader.get_from_pos(b"key3", 1).unwrap(), b"value53");
+    match cdb_reader.get_from_pos(b"key3", 2) {
+        Err(Error::OccurrenceOutOfRange { available: 2 }) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    match cdb_reader.get_first(b"missing") {
+        Err(Error::KeyNotInCDB) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn abort_skips_the_footer() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.abort().unwrap();
+    }
+    // Only the zeroed header and the record, no hash tables.
+    assert_eq!(c.get_ref().len(), 2048 + 8 + 3 + 5);
+    assert!(c.get_ref()[..2048].iter().all(|&b| b == 0));
+
+    let filename = "abort_and_truncate.cdb";
+    let journal = "abort_and_truncate.journal";
+    {
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)
+            .unwrap();
+        let mut cdb_writer = Writer::with_journal(&mut f, journal).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.abort_and_truncate().unwrap();
+    }
+    assert_eq!(fs::metadata(filename).unwrap().len(), 0);
+    assert!(!Path::new(journal).exists());
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn writer_entry_inserts_absent_keys_only() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    // A different key of the same length is still absent.
+    assert!(cdb_writer.put_if_absent(b"kez", b"other").unwrap());
+    assert!(!cdb_writer.put_if_absent(b"key", b"again").unwrap());
+
+    let entry = cdb_writer.entry(b"new").unwrap();
+    assert!(!entry.is_occupied());
+    assert!(entry.or_insert_with(|| b"lazy".to_vec()).unwrap());
+    let entry = cdb_writer.entry(b"new").unwrap();
+    assert!(entry.is_occupied());
+    assert!(!entry.or_insert_with(|| panic!("not called")).unwrap());
+    assert_eq!(cdb_writer.len(), 3);
+
+    let mut cdb_reader = cdb_writer.as_reader().unwrap();
+    assert_eq!(cdb_reader.get(b"key"), vec![b"value".to_vec()]);
+    assert_eq!(cdb_reader.get(b"kez"), vec![b"other".to_vec()]);
+    assert_eq!(cdb_reader.get(b"new"), vec![b"lazy".to_vec()]);
+}
+
+#[test]
+fn cdb_read_borrows_from_memory() {
+    fn pairs<R: CdbRead>(cdb_reader: &mut R) -> Vec<(Vec<u8>, Vec<u8>)> {
+        cdb_reader.iter().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+    }
+
+    let bytes = fs::read("tests/testdata/pwdump.cdb").unwrap();
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let expected: Vec<_> = (&mut cdb_reader).into_iter().collect();
+    assert_eq!(pairs(&mut cdb_reader), expected);
+
+    let mut shared = SharedReader::new(&bytes[..]).unwrap();
+    assert_eq!(pairs(&mut shared), expected);
+    let (key, value) = shared.iter().next().unwrap();
+    match (key, value) {
+        (Cow::Borrowed(_), Cow::Borrowed(_)) => {}
+        other => panic!("expected borrowed pairs, got {:?}", other),
+    }
+
+    let f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut shared = SharedReader::new(f).unwrap();
+    assert_eq!(pairs(&mut shared), expected);
+    assert!(shared.iter().all(|(k, _)| match k {
+        Cow::Owned(_) => true,
+        Cow::Borrowed(_) => false,
+    }));
+}
+
+#[test]
+fn rewrite_with_deletes_drops_keys() {
+    let mut src = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut src).unwrap();
+        for (k, v) in &[("a", "1"), ("b", "2"), ("a", "3"), ("c", "4")] {
+            cdb_writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+    }
+    let mut deletes = HashSet::new();
+    deletes.insert(b"b".to_vec());
+    deletes.insert(b"missing".to_vec());
+
+    let mut dst = Cursor::new(vec![]);
+    let mut src_reader = Reader::new(&mut src).unwrap();
+    let mut dst_writer = Writer::new(&mut dst).unwrap();
+    let copied = rewrite_with_deletes(&mut src_reader, &deletes, &mut dst_writer).unwrap();
+    assert_eq!(copied, 3);
+
+    let mut dst_reader = dst_writer.as_reader().unwrap();
+    assert_eq!(dst_reader.len(), 3);
+    assert_eq!(dst_reader.get(b"a"), vec![b"1".to_vec(), b"3".to_vec()]);
+    assert!(dst_reader.get(b"b").is_empty());
+    assert_eq!(dst_reader.get(b"c"), vec![b"4".to_vec()]);
+}
+
+#[test]
+fn rw_cdb_alternates_reads_and_writes() {
+    let filename = "rw_cdb_alternates.cdb";
+    let mut f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(filename)
+        .unwrap();
+    {
+        let mut cdb = RwCdb::create(&mut f).unwrap();
+        for batch in 0..5 {
+            let cdb_writer = cdb.write().unwrap();
+            for i in 0..10 {
+                let key = format!("key{}", i);
+                cdb_writer.put(key.as_bytes(), format!("{}", batch).as_bytes()).unwrap();
+            }
+            let cdb_reader = cdb.read().unwrap();
+            assert_eq!(cdb_reader.len(), (batch + 1) * 10);
+            assert_eq!(cdb_reader.get(b"key3").len(), batch + 1);
+        }
+        let mut cdb_reader = cdb.into_reader().unwrap();
+        assert_eq!(cdb_reader.get_from_pos(b"key9", 4).unwrap(), b"4");
+        assert_eq!(cdb_reader.into_iter().count(), 50);
+    }
+    // Rebuilding the footer doesn't accumulate empty slots.
+    assert_eq!(f.metadata().unwrap().len(), 2048 + 50 * (8 + 4 + 1 + 16));
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn value_len_without_reading_the_value() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"big", &[7; 10_000]).unwrap();
+        cdb_writer.put(b"big", b"second").unwrap();
+        cdb_writer.put(b"empty", b"").unwrap();
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.value_len(b"big").unwrap(), 10_000);
+    assert_eq!(cdb_reader.value_len(b"empty").unwrap(), 0);
+    match cdb_reader.value_len(b"missing") {
+        Err(Error::KeyNotInCDB) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Probes slots in the reverse order of `LinearProbe`.
+#[derive(Debug)]
+struct ReverseProbe;
+
+impl ProbeSequence for ReverseProbe {
+    fn slot(&self, first: u32, attempt: u32, nslots: u32) -> u32 {
+        (first + nslots - attempt % nslots) % nslots
+    }
+}
+
+#[test]
+fn custom_probe_sequence_round_trips() {
+    let pairs: Vec<(String, String)> = (0..500)
+        .map(|i| (format!("key{}", i % 300), format!("value{}", i)))
+        .collect();
+    let write = |probing: &'static dyn ProbeSequence| {
+        let mut c = Cursor::new(vec![]);
+        {
+            let mut cdb_writer = Writer::new(&mut c).unwrap();
+            cdb_writer.set_probing(probing);
+            for (k, v) in &pairs {
+                cdb_writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+        }
+        c.into_inner()
+    };
+    let mut linear = Cursor::new(write(&LinearProbe));
+    let mut default = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut default).unwrap();
+        for (k, v) in &pairs {
+            cdb_writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+    }
+    assert_eq!(linear.get_ref(), default.get_ref());
+
+    let reverse = write(&ReverseProbe);
+    assert_ne!(&reverse, linear.get_ref());
+    let mut reverse = Cursor::new(reverse);
+    let mut cdb_reader = Reader::new(&mut reverse).unwrap();
+    cdb_reader.set_probing(&ReverseProbe);
+    let mut linear_reader = Reader::new(&mut linear).unwrap();
+    for i in 0..300 {
+        let key = format!("key{}", i);
+        assert_eq!(cdb_reader.get(key.as_bytes()), linear_reader.get(key.as_bytes()));
+    }
+}
+
+#[test]
+fn custom_hasher_round_trip() {
+    #[derive(Debug)]
+    struct Fnv1a;
+    impl Hasher for Fnv1a {
+        fn hash(&self, key: &[u8]) -> u32 {
+            key.iter().fold(0x811c_9dc5, |h: u32, &b| (h ^ u32::from(b)).wrapping_mul(0x0100_0193))
+        }
+    }
+    assert_eq!(DjbHasher.hash(b"key"), hash(b"key"));
+
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::with_hasher(&mut c, &Fnv1a).unwrap();
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            cdb_writer.put(key.as_bytes(), i.to_string().as_bytes()).unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::with_hasher(&mut c, &Fnv1a).unwrap();
+    assert_eq!(cdb_reader.len(), 100);
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        assert_eq!(cdb_reader.get(key.as_bytes()), vec![i.to_string().into_bytes()]);
+    }
+    let mut djb_reader = Reader::new(&mut c).unwrap();
+    let found = (0..100)
+        .filter(|i| !djb_reader.get(format!("key{}", i).as_bytes()).is_empty())
+        .count();
+    assert!(found < 100);
+
+    let shared = SharedReader::with_hasher(c.get_ref().clone(), &Fnv1a).unwrap();
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        assert_eq!(shared.get_first(key.as_bytes()).unwrap(), i.to_string().as_bytes());
+    }
+
+    let mut buffered = BufferedWriter::with_hasher(vec![], &Fnv1a);
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        buffered.put(key.as_bytes(), i.to_string().as_bytes()).unwrap();
+    }
+    assert_eq!(&buffered.finish().unwrap(), c.get_ref());
+
+    let filename = "custom_hasher_append.cdb";
+    fs::write(filename, c.get_ref()).unwrap();
+    {
+        let mut cdb_writer = Writer::append_with_hasher(filename, &Fnv1a).unwrap();
+        cdb_writer.put(b"appended", b"100").unwrap();
+    }
+    let mut f = File::open(filename).unwrap();
+    let mut cdb_reader = Reader::with_hasher(&mut f, &Fnv1a).unwrap();
+    assert_eq!(cdb_reader.len(), 101);
+    assert_eq!(cdb_reader.get(b"key7"), vec![b"7".to_vec()]);
+    assert_eq!(cdb_reader.get(b"appended"), vec![b"100".to_vec()]);
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn vec2str_placeholders() {
+    let inputs: [&[u8]; 6] = [
+        b"",
+        b"plain",
+        b"\xff",
+        b"a\xe2\x82b",
+        b"\xf0\x9f\x92",
+        b"\xc3\xa9\xff",
+    ];
+    for input in &inputs {
+        assert_eq!(vec2str(input), String::from_utf8_lossy(input));
+    }
+    assert_eq!(vec2str_with(b"a\xe2\x82b", '?'), "a?b");
+    assert_eq!(vec2str_with(b"\xc3\xa9\xff\xfe", '?'), "\u{e9}??");
+    assert_eq!(vec2str_with(b"end\xf0\x9f\x92", '_'), "end_");
+}
+
+#[test]
+fn rewriting_fixtures_is_byte_identical() {
+    for filename in &["tests/testdata/top250pws.cdb", "tests/testdata/pwdump.cdb"] {
+        let original = fs::read(filename).unwrap();
+        let mut f = File::open(filename).unwrap();
+        let cdb_reader = Reader::new(&mut f).unwrap();
+        let mut c = Cursor::new(vec![]);
+        {
+            let mut cdb_writer = Writer::new(&mut c).unwrap();
+            for (k, v) in cdb_reader.into_iter() {
+                cdb_writer.put(&k, &v).unwrap();
+            }
+        }
+        assert_eq!(c.get_ref(), &original, "{}", filename);
+    }
+}
+
+#[test]
+fn reader_from_file_matches_new() {
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let expected: Vec<_> = Reader::new(&mut f).unwrap().into_iter().collect();
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let cdb_reader = Reader::from_file(&mut f).unwrap();
+    assert_eq!(cdb_reader.total_size(), fs::metadata("tests/testdata/pwdump.cdb").unwrap().len());
+    assert_eq!(cdb_reader.into_iter().collect::<Vec<_>>(), expected);
+
+    let filename = "reader_from_file_too_small.cdb";
+    fs::write(filename, [0; 100]).unwrap();
+    let mut f = File::open(filename).unwrap();
+    match Reader::from_file(&mut f) {
+        Err(Error::CDBTooSmall { size: 100 }) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn locate_and_read_handle() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let handles: Vec<ValueHandle> = ["letmein", "password"]
+        .iter()
+        .map(|key| cdb_reader.locate(key.as_bytes()).unwrap())
+        .collect();
+    assert_eq!(handles[0].len, 2);
+    assert_eq!(cdb_reader.read_handle(handles[0]).unwrap(), b"10");
+    assert_eq!(
+        cdb_reader.read_handle(handles[1]).unwrap(),
+        cdb_reader.get_first(b"password").unwrap()
+    );
+    match cdb_reader.locate(b"not a password") {
+        Err(Error::KeyNotInCDB) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    let past_records = ValueHandle {
+        offset: cdb_reader.size_breakdown().records + 2048,
+        len: 1,
+    };
+    match cdb_reader.read_handle(past_records) {
+        Err(Error::InvalidOffset { .. }) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn contains_key() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let keys: Vec<Vec<u8>> = cdb_reader.keys();
+    for key in &keys {
+        assert!(cdb_reader.contains_key(key).unwrap());
+    }
+    assert!(!cdb_reader.contains_key(b"not a password").unwrap());
+    assert!(!cdb_reader.contains_key(b"").unwrap());
+}
+
+#[test]
+fn cdb64_round_trip() {
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (0..100u32)
+        .map(|i| (format!("key{}", i % 40).into_bytes(), format!("value{}", i).into_bytes()))
+        .collect();
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new_with_format(&mut c, Format::Cdb64).unwrap();
+        for (k, v) in &items {
+            cdb_writer.put(k, v).unwrap();
+        }
+    }
+    let records: usize = items.iter().map(|(k, v)| 16 + k.len() + v.len()).sum();
+    assert_eq!(c.get_ref().len(), 4096 + records + 16 * 2 * items.len());
+
+    let mut cdb_reader = Reader::new_with_format(&mut c, Format::Cdb64).unwrap();
+    assert_eq!(cdb_reader.len(), items.len());
+    assert_eq!(
+        cdb_reader.get(b"key3"),
+        vec![b"value3".to_vec(), b"value43".to_vec(), b"value83".to_vec()]
+    );
+    assert_eq!(cdb_reader.get_from_pos(b"key3", 2).unwrap(), b"value83");
+    assert!(!cdb_reader.contains_key(b"key40").unwrap());
+    assert_eq!((&mut cdb_reader).into_iter().collect::<Vec<_>>(), items);
+    assert!(cdb_reader.verify().unwrap().is_ok());
+}
+
+#[test]
+fn reader_open_owns_the_file() {
+    fn open_passwords() -> Reader<'static, File> {
+        Reader::open("tests/testdata/top250pws.cdb").unwrap()
+    }
+    let mut cdb_reader = open_passwords();
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut borrowed = Reader::new(&mut f).unwrap();
+    assert_eq!(cdb_reader.len(), borrowed.len());
+    assert_eq!(cdb_reader.keys(), borrowed.keys());
+    assert!(Reader::open("tests/testdata/missing.cdb").is_err());
+}
+
+#[test]
+fn get_from_pos_on_many_duplicates() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..20 {
+            cdb_writer.put(b"key", i.to_string().as_bytes()).unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.table_info((hash(b"key") & 0xff) as u8).unwrap().occupied, 20);
+    assert_eq!(cdb_reader.get_from_pos(b"key", 19).unwrap(), b"19");
+    match cdb_reader.get_from_pos(b"key", 20) {
+        Err(Error::OccurrenceOutOfRange { available }) => assert_eq!(available, 20),
+        other => panic!("Expected OccurrenceOutOfRange, got {:?}", other),
+    }
+}
+
+#[test]
+fn writer_into_bytes() {
+    let mut cdb_writer = Writer::in_memory().unwrap();
+    for i in 0..10 {
+        let s = i.to_string();
+        cdb_writer.put(s.as_bytes(), s.as_bytes()).unwrap();
+    }
+    let bytes = cdb_writer.into_bytes().unwrap();
+
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..10 {
+            let s = i.to_string();
+            cdb_writer.put(s.as_bytes(), s.as_bytes()).unwrap();
+        }
+    }
+    assert_eq!(bytes, *c.get_ref());
+
+    let mut borrowed = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut borrowed).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    let bytes = cdb_writer.into_bytes().unwrap();
+    assert!(borrowed.get_ref().is_empty());
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    assert_eq!(cdb_reader.get(b"key"), vec![b"value".to_vec()]);
+}
+
+/// A file that fails every write once `limit` bytes have been written.
+struct FullDisk {
+    inner: Cursor<Vec<u8>>,
+    limit: u64,
+}
+
+impl Read for FullDisk {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for FullDisk {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.inner.position() + buf.len() as u64 > self.limit {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for FullDisk {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn finalize_records_reports_footer_errors() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.abort().unwrap();
+    }
+    let records = c.into_inner();
+    assert_eq!(records.len(), 2048 + 8 + 3 + 5);
+
+    let mut c = Cursor::new(records.clone());
+    Writer::finalize_records(&mut c).unwrap();
+    assert_eq!(Reader::new(&mut c).unwrap().get(b"key"), vec![b"value".to_vec()]);
+
+    // There's no room for the footer.
+    let mut f = FullDisk {
+        limit: records.len() as u64,
+        inner: Cursor::new(records),
+    };
+    match Writer::finalize_records(&mut f) {
+        Err(Error::IOError(_)) => {}
+        other => panic!("Expected IOError, got {:?}", other),
+    }
+}
+
+#[test]
+fn finalize_records_rejects_records_past_the_end() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.put(b"other", b"value").unwrap();
+        cdb_writer.abort().unwrap();
+    }
+    let records = c.into_inner();
+    let second = 2048 + 8 + 3 + 5;
+
+    // A garbage key length is rejected before it's allocated.
+    let mut garbage = records.clone();
+    garbage[second..second + 4].copy_from_slice(&pack(0xffff_ffff));
+    for bytes in [garbage, records[..second + 4].to_vec(), records[..second + 10].to_vec()] {
+        match Writer::finalize_records(&mut Cursor::new(bytes)) {
+            Err(Error::CorruptRecord { position }) => assert_eq!(position, second as u64),
+            other => panic!("Expected CorruptRecord, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn close_reports_finalize_errors() {
+    let mut c = Cursor::new(vec![]);
+    let mut cdb_writer = Writer::new(&mut c).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    cdb_writer.close().unwrap();
+    assert_eq!(Reader::new(&mut c).unwrap().get(b"key"), vec![b"value".to_vec()]);
+
+    let mut f = FullDisk {
+        inner: Cursor::new(vec![]),
+        limit: 2048 + 16,
+    };
+    let mut cdb_writer = Writer::new(&mut f).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    match cdb_writer.close() {
+        Err(Error::IOError(_)) => {}
+        other => panic!("Expected IOError, got {:?}", other),
+    }
+
+    // Dropping a `Writer` that can't write its footer doesn't panic.
+    let mut cdb_writer = Writer::new(&mut f).unwrap();
+    cdb_writer.put(b"key", b"value").unwrap();
+    drop(cdb_writer);
+}
+
+#[test]
+fn unique_keys_in_first_seen_order() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for key in &["b", "a", "b", "c", "a", "b"] {
+            cdb_writer.put(key.as_bytes(), b"value").unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.keys().len(), 6);
+    assert_eq!(
+        cdb_reader.unique_keys(),
+        vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]
+    );
+}
+
+#[test]
+fn shared_reader_from_bytes() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut bytes = vec![];
+    File::open("tests/testdata/top250pws.cdb")
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    let cdb_reader = std::sync::Arc::new(SharedReader::from_bytes(bytes).unwrap());
+    assert_send_sync(&cdb_reader);
+
+    let shared = std::sync::Arc::clone(&cdb_reader);
+    let found = thread::spawn(move || {
+        (
+            shared.contains_key(b"letmein").unwrap(),
+            shared.contains_key(b"not a password").unwrap(),
+        )
+    });
+    assert_eq!(found.join().unwrap(), (true, false));
+    assert_eq!(cdb_reader.get_first(b"letmein").unwrap(), b"10");
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_reader_matches_file_reader() {
+    let mut mapped = Reader::mmap("tests/testdata/top250pws.cdb").unwrap();
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    assert_eq!(mapped.len(), cdb_reader.len());
+    for key in cdb_reader.keys() {
+        assert_eq!(mapped.get(&key), cdb_reader.get(&key));
+    }
+    assert!(mapped.get(b"not a password").is_empty());
+    assert!(Reader::mmap("tests/testdata/missing.cdb").is_err());
+}
+
+#[test]
+fn get_into_reuses_the_buffer() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let mut buf = Vec::with_capacity(64);
+    let capacity = buf.capacity();
+    for key in cdb_reader.keys() {
+        let len = cdb_reader.get_into(&key, 0, &mut buf).unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(buf, cdb_reader.get_from_pos(&key, 0).unwrap());
+    }
+    assert_eq!(buf.capacity(), capacity);
+    match cdb_reader.get_into(b"not a password", 0, &mut buf) {
+        Err(Error::KeyNotInCDB) => assert!(buf.is_empty()),
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+}
+
+#[test]
+fn records_past_4gb_are_rejected() {
+    let filename = "record_too_large.cdb";
+    let table_start = u64::from(u32::MAX) - 10;
+    {
+        // A sparse file whose records end just before the 4GB limit.
+        let mut f = File::create(filename).unwrap();
+        for _ in 0..256 {
+            f.write_all(&pack(table_start as u32)).unwrap();
+            f.write_all(&pack(0)).unwrap();
+        }
+        f.set_len(table_start).unwrap();
+    }
+    {
+        let mut f = OpenOptions::new().read(true).write(true).open(filename).unwrap();
+        let mut cdb_writer = Reader::new(&mut f).unwrap().as_writer().unwrap();
+        match cdb_writer.put(b"key", b"value") {
+            Err(Error::RecordTooLarge { size }) => assert_eq!(size, table_start + 16),
+            other => panic!("Expected RecordTooLarge, got {:?}", other),
+        }
+        cdb_writer.put(b"", b"").unwrap();
+        assert_eq!(cdb_writer.len(), 1);
+        // The record fits, but the hash tables after its table don't.
+        match cdb_writer.close() {
+            Err(Error::CDBTooBig { size }) => assert_eq!(size, table_start + 8 + 16),
+            other => panic!("Expected CDBTooBig, got {:?}", other),
+        }
+    }
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn corrupt_record_pointers_in_lookups() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+    }
+    let bytes = c.into_inner();
+    let (h, table) = (hash(b"key"), (hash(b"key") & 0xff) as usize * 8);
+    let table_pos = u32::from_le_bytes([
+        bytes[table],
+        bytes[table + 1],
+        bytes[table + 2],
+        bytes[table + 3],
+    ]) as usize;
+    let slot = table_pos + ((h >> 8) % 2) as usize * 8;
+    assert_eq!(&bytes[slot..slot + 4], &pack(h)[..]);
+
+    // Pointing the slot inside the header, past the records, or making the
+    // record run into the hash tables, is corruption, not a missing key.
+    let mut into_header = bytes.clone();
+    into_header[slot + 4..slot + 8].copy_from_slice(&pack(16));
+    let mut past_records = bytes.clone();
+    past_records[slot + 4..slot + 8].copy_from_slice(&pack(table_pos as u32));
+    let mut too_long = bytes.clone();
+    too_long[2048 + 4..2048 + 8].copy_from_slice(&pack(1000));
+    for corrupt in &[into_header, past_records, too_long] {
+        let mut cdb_reader = Reader::from_bytes(corrupt.clone()).unwrap();
+        match cdb_reader.get_first(b"key") {
+            Err(Error::CorruptRecord { .. }) => {}
+            other => panic!("Expected CorruptRecord, got {:?}", other),
+        }
+        let shared = SharedReader::new(corrupt.clone()).unwrap();
+        match shared.get_first(b"key") {
+            Err(Error::CorruptRecord { .. }) => {}
+            other => panic!("Expected CorruptRecord, got {:?}", other),
+        }
+    }
+
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    match cdb_reader.get_first(b"not a key") {
+        Err(Error::KeyNotInCDB) => {}
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+}
+
+#[test]
+fn atomic_writer_replaces_on_commit_only() {
+    let filename = "atomic_writer.cdb";
+    {
+        let mut cdb_writer = Writer::atomic(filename).unwrap();
+        cdb_writer.put(b"key", b"old").unwrap();
+        cdb_writer.commit().unwrap();
+    }
+    {
+        let mut cdb_writer = Writer::atomic(filename).unwrap();
+        cdb_writer.put(b"key", b"new").unwrap();
+        // Readers see the old CDB until the new one is committed.
+        assert_eq!(Reader::open(filename).unwrap().get(b"key"), vec![b"old".to_vec()]);
+    }
+    assert_eq!(Reader::open(filename).unwrap().get(b"key"), vec![b"old".to_vec()]);
+    let leftovers = fs::read_dir(".")
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with("atomic_writer.cdb.tmp")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let mut cdb_writer = Writer::atomic(filename).unwrap();
+    cdb_writer.put(b"key", b"new").unwrap();
+    cdb_writer.commit().unwrap();
+    assert_eq!(Reader::open(filename).unwrap().get(b"key"), vec![b"new".to_vec()]);
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn atomic_writers_on_the_same_path() {
+    let filename = "atomic_writers.cdb";
+    let mut first = Writer::atomic(filename).unwrap();
+    let mut second = Writer::atomic(filename).unwrap();
+    first.put(b"key", b"first").unwrap();
+    second.put(b"key", b"second").unwrap();
+    // Each one builds in its own temporary file.
+    first.commit().unwrap();
+    assert_eq!(Reader::open(filename).unwrap().get(b"key"), vec![b"first".to_vec()]);
+    second.commit().unwrap();
+    assert_eq!(Reader::open(filename).unwrap().get(b"key"), vec![b"second".to_vec()]);
+    fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn verify_cross_checks_the_hash_tables() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.put(b"other", b"value").unwrap();
+    }
+    let bytes = c.into_inner();
+    let (h, bucket) = (hash(b"key"), (hash(b"key") & 0xff) as usize);
+    let table_pos = u32::from_le_bytes([
+        bytes[bucket * 8],
+        bytes[bucket * 8 + 1],
+        bytes[bucket * 8 + 2],
+        bytes[bucket * 8 + 3],
+    ]) as usize;
+    let slot = table_pos + ((h >> 8) % 2) as usize * 8;
+
+    let mut wrong_hash = bytes.clone();
+    wrong_hash[slot..slot + 4].copy_from_slice(&pack(h ^ 0x100));
+    let report = Reader::from_bytes(wrong_hash).unwrap().verify().unwrap();
+    assert_eq!(report.anomalies, vec![Anomaly::UnreachableRecord { position: 2048 }]);
+
+    let mut mid_record = bytes;
+    mid_record[slot + 4..slot + 8].copy_from_slice(&pack(2049));
+    let report = Reader::from_bytes(mid_record).unwrap().verify().unwrap();
+    assert_eq!(report.records, 2);
+    assert_eq!(
+        report.anomalies,
+        vec![
+            Anomaly::UnreachableRecord { position: 2048 },
+            Anomaly::DanglingSlot {
+                bucket,
+                position: 2049
+            },
+        ]
+    );
+}
+
+#[test]
+fn dump_is_byte_exact() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.put(b"\xff\x00", b"\n->").unwrap();
+        cdb_writer.put(b"", b"").unwrap();
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    let mut out = vec![];
+    cdb_reader.dump(&mut out).unwrap();
+    assert_eq!(out, b"+3,5:key->value\n+2,3:\xff\x00->\n->\n+0,0:->\n\n".to_vec());
+
+    let mut c = Cursor::new(vec![]);
+    Writer::write_empty(&mut c).unwrap();
+    let mut out = vec![];
+    Reader::new(&mut c).unwrap().dump(&mut out).unwrap();
+    assert_eq!(out, b"\n");
+}
+
+#[test]
+fn load_from_cdbmake_dump() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut dump = vec![];
+    Reader::new(&mut f).unwrap().dump(&mut dump).unwrap();
+
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        assert_eq!(cdb_writer.load_from(&mut &dump[..]).unwrap(), 250);
+    }
+    let mut reloaded = vec![];
+    Reader::new(&mut c).unwrap().dump(&mut reloaded).unwrap();
+    assert_eq!(reloaded, dump);
+
+    let malformed: Vec<(&[u8], u64, &str)> = vec![
+        (b"+3,5:key->value\n", 16, "`+` or an empty line"),
+        (b"-3,5:key->value\n\n", 0, "`+` or an empty line"),
+        (b"+,5:key->value\n\n", 1, "a length"),
+        (b"+3;5:key->value\n\n", 2, "a length"),
+        (b"+3,5:key=>value\n\n", 8, "`->`"),
+        (b"+3,9:key->value\n\n", 17, "the value"),
+        (b"+3,5:key->values\n\n", 15, "a newline"),
+    ];
+    for (input, offset, expected) in malformed {
+        let mut c = Cursor::new(vec![]);
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        match cdb_writer.load_from(&mut &input[..]) {
+            Err(Error::MalformedDump {
+                offset: o,
+                expected: e,
+            }) => assert_eq!((o, e), (offset, expected), "{:?}", vec2str(input)),
+            other => panic!("Expected MalformedDump, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn put_many_and_extend() {
+    let mut map = HashMap::new();
+    map.insert(b"one".to_vec(), b"1".to_vec());
+    map.insert(b"two".to_vec(), b"2".to_vec());
+
+    let mut cdb_writer = Writer::in_memory().unwrap();
+    assert_eq!(cdb_writer.put_many(vec![("a", "x"), ("a", "y")]).unwrap(), 2);
+    assert_eq!(cdb_writer.put_many(Vec::<(&[u8], &[u8])>::new()).unwrap(), 0);
+    cdb_writer.extend(map);
+    let mut cdb_reader = Reader::from_bytes(cdb_writer.into_bytes().unwrap()).unwrap();
+    assert_eq!(cdb_reader.len(), 4);
+    assert_eq!(cdb_reader.get(b"a"), vec![b"x".to_vec(), b"y".to_vec()]);
+    assert_eq!(cdb_reader.get(b"one"), vec![b"1".to_vec()]);
+    assert_eq!(cdb_reader.get(b"two"), vec![b"2".to_vec()]);
+
+    // Stops at the first error, here a key whose DJB hash is `0`.
+    let mut cdb_writer = Writer::in_memory().unwrap();
+    cdb_writer.set_strict(true);
+    let items: Vec<(&[u8], &[u8])> = vec![(b"ok", b""), (b"\x97\xc3aue", b""), (b"after", b"")];
+    match cdb_writer.put_many(items) {
+        Err(Error::UnrepresentableKey) => (),
+        other => panic!("Expected UnrepresentableKey, got {:?}", other),
+    }
+    let cdb_reader = Reader::from_bytes(cdb_writer.into_bytes().unwrap()).unwrap();
+    assert_eq!(cdb_reader.len(), 1);
+}
+
+#[test]
+fn stats_report_fill_and_probe_distance() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let stats = cdb_reader.stats().unwrap();
+    assert_eq!(stats.records, cdb_reader.len());
+    assert_eq!(stats.tables.len(), 256);
+    for (bucket, table) in stats.tables.iter().enumerate() {
+        let info = cdb_reader.table_info(bucket as u8).unwrap();
+        assert_eq!((table.slots, table.occupied), (info.slots, info.occupied));
+        assert!(table.max_probe_distance <= stats.max_probe_distance);
+    }
+
+    // Every key lands in the same table and wants the same first slot.
+    #[derive(Debug)]
+    struct Constant;
+    impl Hasher for Constant {
+        fn hash(&self, _key: &[u8]) -> u32 {
+            0x100
+        }
+    }
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::with_hasher(&mut c, &Constant).unwrap();
+        for i in 0..10 {
+            cdb_writer.put(i.to_string().as_bytes(), b"").unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::with_hasher(&mut c, &Constant).unwrap();
+    let stats: Stats = cdb_reader.stats().unwrap();
+    assert_eq!(stats.records, 10);
+    assert_eq!(stats.max_probe_distance, 9);
+    assert_eq!(stats.tables[0].slots, 20);
+    assert_eq!(stats.tables[0].fill_ratio, 0.5);
+    assert!(stats.tables[1..].iter().all(|t| t.slots == 0 && t.fill_ratio == 0.0));
+}
+
+#[test]
+fn append_rebuilds_the_index_from_the_records() {
+    let filename = "append_rebuilds_the_index_from_the_records.cdb";
+    let mut expected = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut expected).unwrap();
+        for i in 0..300 {
+            let s = (i % 97).to_string();
+            cdb_writer.put(s.as_bytes(), i.to_string().as_bytes()).unwrap();
+        }
+    }
+    {
+        let mut f = File::create(filename).unwrap();
+        let mut cdb_writer = Writer::new(&mut f).unwrap();
+        for i in 0..150 {
+            let s = (i % 97).to_string();
+            cdb_writer.put(s.as_bytes(), i.to_string().as_bytes()).unwrap();
+        }
+    }
+    // Damage the footer, which `append` doesn't read.
+    let len = fs::metadata(filename).unwrap().len();
+    OpenOptions::new().write(true).open(filename).unwrap().set_len(len - 8).unwrap();
+    {
+        let mut cdb_writer = Writer::append(filename).unwrap();
+        for i in 150..300 {
+            let s = (i % 97).to_string();
+            cdb_writer.put(s.as_bytes(), i.to_string().as_bytes()).unwrap();
+        }
+        cdb_writer.close().unwrap();
+    }
+    assert_eq!(&fs::read(filename).unwrap(), expected.get_ref());
+    let _ = fs::remove_file(filename);
+}
+
+#[test]
+fn owned_reader_into_iter() {
+    let bytes = fs::read("tests/testdata/pwdump.cdb").unwrap();
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    let borrowed: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+    // Lookups move the file position, the owned iterator still starts over.
+    cdb_reader.get_first(&borrowed[10].0).unwrap();
+    let mut owned = vec![];
+    for (k, v) in cdb_reader {
+        owned.push((k, v));
+    }
+    assert_eq!(owned, borrowed);
+}
+
+#[test]
+fn to_map_and_from_map() {
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let map = cdb_reader.to_map();
+    assert_eq!(map.len(), cdb_reader.unique_keys().len());
+    assert_eq!(map.values().map(Vec::len).sum::<usize>(), cdb_reader.len());
+    for (key, values) in map.iter().take(20) {
+        assert_eq!(&cdb_reader.get(key), values);
+    }
+
+    let mut c = Cursor::new(vec![]);
+    Writer::from_map(&mut c, &map).unwrap().close().unwrap();
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.to_map(), map);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn cdb_map_serde_round_trip() {
+    use galvanize::CdbMap;
+    use std::collections::BTreeMap;
+
+    let mut map = BTreeMap::new();
+    map.insert(b"\xff\x00key".to_vec(), vec![b"a".to_vec(), b"\xfe".to_vec()]);
+    map.insert(b"other".to_vec(), vec![vec![]]);
+    let map = CdbMap(map);
+
+    let json = serde_json::to_string(&map).unwrap();
+    assert_eq!(json, r#"[[[111,116,104,101,114],[[]]],[[255,0,107,101,121],[[97],[254]]]]"#);
+    assert_eq!(serde_json::from_str::<CdbMap>(&json).unwrap(), map);
+
+    // Repeated keys are merged.
+    let merged: CdbMap = serde_json::from_str("[[[1],[[2]]],[[1],[[3]]]]").unwrap();
+    assert_eq!(merged.0[&vec![1]], vec![vec![2], vec![3]]);
+}
+
+#[test]
+fn lookup_returns_none_for_missing_keys() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"first").unwrap();
+        cdb_writer.put(b"key", b"second").unwrap();
+        cdb_writer.put(b"empty", b"").unwrap();
+    }
+    let bytes = c.into_inner();
+    let mut cdb_reader = Reader::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(cdb_reader.lookup(b"key").unwrap(), Some(b"first".to_vec()));
+    assert_eq!(cdb_reader.lookup(b"empty").unwrap(), Some(vec![]));
+    assert_eq!(cdb_reader.lookup(b"missing").unwrap(), None);
+
+    // The first record claims a value running into the hash tables.
+    let mut corrupt = bytes;
+    corrupt[2048 + 4..2048 + 8].copy_from_slice(&pack(1000));
+    let mut cdb_reader = Reader::from_bytes(corrupt).unwrap();
+    match cdb_reader.lookup(b"key") {
+        Err(Error::CorruptRecord { .. }) => {}
+        other => panic!("Expected CorruptRecord, got {:?}", other),
+    }
+}
+
+#[test]
+fn scan_buffered_matches_into_iter() {
+    for filename in &["tests/testdata/top250pws.cdb", "tests/testdata/pwdump.cdb"] {
+        let mut f = File::open(filename).unwrap();
+        let mut cdb_reader = Reader::new(&mut f).unwrap();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+        assert_eq!(cdb_reader.scan_buffered().collect::<Vec<_>>(), expected);
+        // The file is left usable for lookups.
+        assert_eq!(cdb_reader.get(&expected[0].0)[0], expected[0].1);
+    }
+
+    // Stops at the record overrunning into the hash tables.
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        cdb_writer.put(b"key", b"value").unwrap();
+        cdb_writer.put(b"other", b"value").unwrap();
+    }
+    let mut bytes = c.into_inner();
+    let second = 2048 + 8 + 3 + 5;
+    bytes[second + 4..second + 8].copy_from_slice(&pack(100));
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    let items: Vec<(Vec<u8>, Vec<u8>)> = cdb_reader.scan_buffered().collect();
+    assert_eq!(items, vec![(b"key".to_vec(), b"value".to_vec())]);
+}
+
+#[test]
+fn with_capacity_writes_the_same_bytes() {
+    let write = |expected_records: Option<usize>| {
+        let mut c = Cursor::new(vec![]);
+        {
+            let mut cdb_writer = match expected_records {
+                Some(n) => Writer::with_capacity(&mut c, n).unwrap(),
+                None => Writer::new(&mut c).unwrap(),
+            };
+            for i in 0..1000 {
+                cdb_writer.put(format!("key{}", i % 300).as_bytes(), b"value").unwrap();
+            }
+        }
+        c.into_inner()
+    };
+    let expected = write(None);
+    for &n in &[0, 10, 1000, 100_000] {
+        assert_eq!(write(Some(n)), expected);
+    }
+}
+
+#[test]
+fn get_last_returns_the_latest_value() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..5 {
+            cdb_writer.put(b"key", i.to_string().as_bytes()).unwrap();
+            cdb_writer.put(b"other", b"x").unwrap();
+        }
+        cdb_writer.put(b"single", b"only").unwrap();
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    assert_eq!(cdb_reader.get_last(b"key").unwrap(), b"4");
+    assert_eq!(cdb_reader.get_last(b"single").unwrap(), b"only");
+    match cdb_reader.get_last(b"missing") {
+        Err(Error::KeyNotInCDB) => {}
+        other => panic!("Expected KeyNotInCDB, got {:?}", other),
+    }
+
+    // Do the same on a CDB with many colliding keys.
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    for key in cdb_reader.unique_keys().iter().take(50) {
+        let values = cdb_reader.get(key);
+        assert_eq!(&cdb_reader.get_last(key).unwrap(), values.last().unwrap());
+    }
+}
+
+#[test]
+fn empty_keys_and_values_round_trip() {
+    assert_eq!(hash(b""), 5381);
+    assert_eq!(hash(b"\x97\xc3aue"), 0);
+    let pairs: [(&[u8], &[u8]); 5] = [
+        (b"", b"value"),
+        (b"key", b""),
+        (b"", b""),
+        (b"key", b"v"),
+        (b"\x97\xc3aue", b"zero"),
+    ];
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for &(k, v) in &pairs {
+            cdb_writer.put(k, v).unwrap();
+        }
+    }
+    let bytes = c.into_inner();
+    let mut cdb_reader = Reader::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(cdb_reader.len(), 5);
+    assert_eq!(cdb_reader.get(b""), vec![b"value".to_vec(), vec![]]);
+    assert_eq!(cdb_reader.get(b"\x97\xc3aue"), vec![b"zero".to_vec()]);
+    assert_eq!(cdb_reader.get(b"key"), vec![vec![], b"v".to_vec()]);
+    assert_eq!(cdb_reader.lookup(b"key").unwrap(), Some(vec![]));
+    assert_eq!(cdb_reader.get_last(b"").unwrap(), b"");
+    assert_eq!(cdb_reader.count(b"").unwrap(), 2);
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (&mut cdb_reader).into_iter().collect();
+    let expected: Vec<(Vec<u8>, Vec<u8>)> =
+        pairs.iter().map(|&(k, v)| (k.to_vec(), v.to_vec())).collect();
+    assert_eq!(items, expected);
+    assert!(cdb_reader.verify().unwrap().is_ok());
+
+    let shared = SharedReader::new(bytes).unwrap();
+    assert_eq!(shared.get(b""), vec![b"value".to_vec(), vec![]]);
+    assert_eq!(shared.get_first(b"key").unwrap(), b"");
+    assert_eq!(shared.get_first(b"\x97\xc3aue").unwrap(), b"zero");
+}
+
+#[test]
+fn record_positions_point_at_each_record() {
+    let mut f = File::open("tests/testdata/top250pws.cdb").unwrap();
+    let mut cdb_reader = Reader::new(&mut f).unwrap();
+    let records: Vec<(u64, Vec<u8>, Vec<u8>)> =
+        cdb_reader.record_positions().collect::<Result<_, _>>().unwrap();
+    assert_eq!(records.len(), 250);
+    assert_eq!(records[0].0, 2048);
+    for pair in records.windows(2) {
+        let (pos, ref k, ref v) = pair[0];
+        assert_eq!(pair[1].0, pos + 8 + k.len() as u64 + v.len() as u64);
+    }
+    for &(pos, ref k, ref v) in records.iter().step_by(37) {
+        let next = cdb_reader.iter_from(pos).unwrap().next().unwrap();
+        assert_eq!((&next.0, &next.1), (k, v));
+    }
+
+    // The record running into the hash tables ends the iteration with an error.
+    let mut bytes = fs::read("tests/testdata/top250pws.cdb").unwrap();
+    let last = records[249].0 as usize;
+    bytes[last + 4..last + 8].copy_from_slice(&pack(100));
+    let mut cdb_reader = Reader::from_bytes(bytes).unwrap();
+    let mut positions = cdb_reader.record_positions();
+    assert_eq!(positions.by_ref().take(249).filter(|r| r.is_ok()).count(), 249);
+    match positions.next() {
+        Some(Err(Error::CorruptRecord { position })) => assert_eq!(position, last as u64),
+        other => panic!("Expected CorruptRecord, got {:?}", other),
+    }
+    assert!(positions.next().is_none());
+}
+
+#[test]
+fn decode_hex_keys() {
+    assert_eq!(decode_key("").unwrap(), b"");
+    assert_eq!(decode_key("6b6579").unwrap(), b"key");
+    assert_eq!(decode_key("00ffFe7A").unwrap(), b"\x00\xff\xfe\x7a");
+    for &(encoded, offset) in &[("6b657", 5), ("6x", 1), ("zz", 0), ("6b+1", 2), ("\u{e9}", 0)] {
+        match decode_key(encoded) {
+            Err(Error::MalformedKey { offset: o }) => assert_eq!(o, offset, "{:?}", encoded),
+            other => panic!("Expected MalformedKey for {:?}, got {:?}", encoded, other),
+        }
+    }
+}
+
+#[test]
+fn convert_to_cdb64_reads_back_identically() {
+    let mut f = File::open("tests/testdata/pwdump.cdb").unwrap();
+    let mut src = Reader::new(&mut f).unwrap();
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut dst = Writer::new_with_format(&mut c, Format::Cdb64).unwrap();
+        assert_eq!(convert_to_cdb64(&mut src, &mut dst).unwrap(), 3000);
+        dst.close().unwrap();
+    }
+    let mut converted = Reader::new_with_format(&mut c, Format::Cdb64).unwrap();
+    assert_eq!(converted.len(), src.len());
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (&mut src).into_iter().collect();
+    assert_eq!((&mut converted).into_iter().collect::<Vec<_>>(), items);
+    for key in src.unique_keys() {
+        assert_eq!(converted.get(&key), src.get(&key));
+    }
+    assert!(converted.verify().unwrap().is_ok());
+
+    // A standard destination would silently stay a 32-bit CDB.
+    let mut c = Cursor::new(vec![]);
+    let mut dst = Writer::new(&mut c).unwrap();
+    match convert_to_cdb64(&mut src, &mut dst) {
+        Err(Error::IOError(ref e)) if e.kind() == std::io::ErrorKind::InvalidInput => {}
+        other => panic!("Expected InvalidInput, got {:?}", other),
+    }
+    assert!(dst.is_empty());
+}
+
+#[test]
+fn tail_with_count_past_the_records() {
+    let mut c = Cursor::new(vec![]);
+    {
+        let mut cdb_writer = Writer::new(&mut c).unwrap();
+        for i in 0..3 {
+            cdb_writer.put(i.to_string().as_bytes(), b"").unwrap();
+        }
+    }
+    let mut cdb_reader = Reader::new(&mut c).unwrap();
+    let keys = |reader: &mut Reader<_>, count| -> Vec<Vec<u8>> {
+        reader.tail(count).map(|(k, _)| k).collect()
+    };
+    assert_eq!(keys(&mut cdb_reader, 10), vec![b"0".to_vec(), b"1".to_vec(), b"2".to_vec()]);
+    assert_eq!(keys(&mut cdb_reader, 3).len(), 3);
+    assert_eq!(keys(&mut cdb_reader, 1), vec![b"2".to_vec()]);
+    assert!(keys(&mut cdb_reader, 0).is_empty());
+}